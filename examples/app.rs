@@ -79,6 +79,9 @@ async fn main() -> Result<()> {
 
     Condey::init()
         .mount("/api/test", routes)
+        .graceful_shutdown(async {
+            let _ = tokio::signal::ctrl_c().await;
+        })
         .listen_at("127.0.0.1:3000")
         .await?;
 
@@ -1,6 +1,6 @@
 use condey::{
-    http::Method,
-    types::{Json, Path, Query},
+    http::{Method, StatusCode},
+    types::{Conditional, ETag, Json, Path, Preconditions, Query},
     Condey, Route, State,
 };
 
@@ -32,6 +32,11 @@ impl Album {
             name: name.to_string(),
         }
     }
+
+    /// Version of the album clients send back in `If-Match` to update it.
+    pub fn etag(&self) -> ETag {
+        ETag::hash(serde_json::to_vec(self).unwrap())
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, PartialOrd)]
@@ -101,8 +106,7 @@ async fn main() -> Result<()> {
                 albums
                     .iter()
                     .find(|album| album.id == id)
-                    .cloned()
-                    .map(Json)
+                    .map(|album| Conditional::new(Json(album.clone())).etag(album.etag()))
             },
         );
 
@@ -128,24 +132,30 @@ async fn main() -> Result<()> {
             },
         );
 
-    let update =
-        Route::builder()
-            .method(Method::PUT)
-            .path("/albums/:id")
-            .with_handler_fn(
-                |Path((id,)): Path<(i32,)>,
-                 db: State<Arc<Database>>,
-                 updated: Json<AlbumWritable>| async move {
-                    let updated = updated.into_inner();
-                    let mut albums = db.inner().albums.lock().await;
-
-                    let album = albums.iter_mut().find(|album| album.id == id)?;
-                    album.name = updated.name;
-                    album.band = updated.band;
-
-                    Some(Json(album.clone()))
-                },
-            );
+    let update = Route::builder()
+        .method(Method::PUT)
+        .path("/albums/:id")
+        .with_handler_fn(
+            |Path((id,)): Path<(i32,)>,
+             db: State<Arc<Database>>,
+             preconditions: Preconditions,
+             updated: Json<AlbumWritable>| async move {
+                let updated = updated.into_inner();
+                let mut albums = db.inner().albums.lock().await;
+
+                let album = albums
+                    .iter_mut()
+                    .find(|album| album.id == id)
+                    .ok_or(StatusCode::NOT_FOUND)?;
+                // rejects updates based on an outdated version of the album
+                preconditions.evaluate(Some(&album.etag()), None)?;
+
+                album.name = updated.name;
+                album.band = updated.band;
+
+                Ok::<_, StatusCode>(Conditional::new(Json(album.clone())).etag(album.etag()))
+            },
+        );
 
     let albums = vec![
         Album::new(1, "Manilla Road", "Crystal Logic"),
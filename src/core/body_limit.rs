@@ -0,0 +1,186 @@
+use crate::{
+    http::{header::CONTENT_LENGTH, Request, Response, StatusCode},
+    Body,
+};
+
+use futures::{ready, Stream};
+use hyper::body::{Bytes, HttpBody};
+use thiserror::Error;
+
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+#[derive(Debug, Error)]
+#[error("request body exceeds the limit of {limit} bytes")]
+pub(crate) struct LengthLimitError {
+    limit: usize,
+}
+
+/// Rejects requests announcing a body over `limit` and caps the body of the
+/// others, so reading past the limit fails with [`LengthLimitError`].
+pub(crate) fn limit_body(req: &mut Request<Body>, limit: usize) -> Result<(), LengthLimitError> {
+    let content_length = req
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|len| len.to_str().ok())
+        .and_then(|len| len.parse::<u64>().ok());
+
+    if content_length.is_some_and(|len| len > limit as u64) {
+        return Err(LengthLimitError { limit });
+    }
+
+    let body = std::mem::replace(req.body_mut(), Body::empty());
+    if body.is_end_stream() {
+        *req.body_mut() = body;
+    } else {
+        *req.body_mut() = Body::wrap_stream(LimitedBody {
+            body,
+            remaining: limit,
+            limit,
+        });
+    }
+
+    Ok(())
+}
+
+pub(crate) fn payload_too_large(err: &LengthLimitError) -> Response<Body> {
+    tracing::info!("{}", err);
+
+    Response::builder()
+        .status(StatusCode::PAYLOAD_TOO_LARGE)
+        .body(Body::empty())
+        .unwrap()
+}
+
+/// Turns a failure to read the request body into a response.
+pub(crate) fn body_error_response(err: hyper::Error) -> Response<Body> {
+    // the limit may be hit below a decoder, which wraps the error again
+    let limit_error =
+        std::iter::successors(std::error::Error::source(&err), |source| source.source())
+            .find_map(|source| source.downcast_ref::<LengthLimitError>());
+
+    match limit_error {
+        Some(err) => payload_too_large(err),
+        None => {
+            tracing::error!("failed to read request body: {}", err);
+            Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::empty())
+                .unwrap()
+        }
+    }
+}
+
+struct LimitedBody {
+    body: Body,
+    remaining: usize,
+    limit: usize,
+}
+
+impl Stream for LimitedBody {
+    type Item = Result<Bytes, Box<dyn std::error::Error + Send + Sync>>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let chunk = match ready!(Pin::new(&mut self.body).poll_data(cx)) {
+            Some(Ok(chunk)) => chunk,
+            Some(Err(err)) => return Poll::Ready(Some(Err(err.into()))),
+            None => return Poll::Ready(None),
+        };
+
+        if chunk.len() > self.remaining {
+            let limit = self.limit;
+            return Poll::Ready(Some(Err(LengthLimitError { limit }.into())));
+        }
+        self.remaining -= chunk.len();
+
+        Poll::Ready(Some(Ok(chunk)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        http::{Method, StatusCode},
+        types::{BodyStream, Json},
+        Body, Condey, Route, ServerConfig, TestClient,
+    };
+
+    #[tokio::test]
+    async fn reject_content_length() {
+        let route = Route::builder()
+            .method(Method::POST)
+            .path("/json")
+            .with_handler_fn(|json: Json<String>| async move { json.into_inner() });
+        let client = TestClient::new(
+            Condey::init()
+                .mount("", vec![route])
+                .server_config(ServerConfig::default().body_limit(16)),
+        );
+
+        client
+            .post("/json")
+            .header("content-length", "17")
+            .body("\"0123456789abcd\"!")
+            .send()
+            .await
+            .assert_status(StatusCode::PAYLOAD_TOO_LARGE);
+        client
+            .post("/json")
+            .body("\"0123456789ab\"")
+            .send()
+            .await
+            .assert_status(StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn reject_chunked() {
+        let route = Route::builder()
+            .method(Method::POST)
+            .path("/json")
+            .with_handler_fn(|json: Json<String>| async move { json.into_inner() });
+        let client = TestClient::new(
+            Condey::init()
+                .mount("", vec![route])
+                .server_config(ServerConfig::default().body_limit(16)),
+        );
+        let chunks = vec![Ok::<_, std::io::Error>("\"0123456789"), Ok("abcdef\"")];
+        let body = Body::wrap_stream(futures::stream::iter(chunks));
+
+        client
+            .post("/json")
+            .body(body)
+            .send()
+            .await
+            .assert_status(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn route_limit() {
+        let route = Route::builder()
+            .method(Method::POST)
+            .path("/upload")
+            .body_limit(32)
+            .with_handler_fn(|body: BodyStream| async move {
+                match hyper::body::to_bytes(body.into_inner()).await {
+                    Ok(body) => body.len().to_string(),
+                    Err(_) => "failed".to_string(),
+                }
+            });
+        let client = TestClient::new(
+            Condey::init()
+                .mount("", vec![route])
+                .server_config(ServerConfig::default().body_limit(16)),
+        );
+
+        let response = client.post("/upload").body(vec![0; 32]).send().await;
+        assert_eq!(response.text().await, "32");
+
+        let chunks = vec![Ok::<_, std::io::Error>(vec![0; 20]), Ok(vec![0; 20])];
+        let body = Body::wrap_stream(futures::stream::iter(chunks));
+
+        let response = client.post("/upload").body(body).send().await;
+        assert_eq!(response.text().await, "failed");
+    }
+}
@@ -0,0 +1,668 @@
+use super::body_limit::{self, LengthLimitError};
+use crate::{
+    http::{
+        header::{
+            ACCEPT_ENCODING, ACCEPT_RANGES, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, ETAG,
+            VARY,
+        },
+        HeaderMap, HeaderValue, Request, Response, StatusCode,
+    },
+    Body,
+};
+
+use async_compression::{
+    tokio::bufread::{
+        BrotliDecoder, BrotliEncoder, GzipDecoder, GzipEncoder, ZlibDecoder, ZlibEncoder,
+        ZstdDecoder, ZstdEncoder,
+    },
+    Level,
+};
+use futures::TryStreamExt;
+use hyper::body::HttpBody;
+use thiserror::Error;
+use tokio::io::{AsyncRead, BufReader};
+use tokio_util::io::{ReaderStream, StreamReader};
+
+use std::{io, pin::Pin};
+
+const DEFAULT_MIN_SIZE: u64 = 1024;
+const DEFAULT_CONTENT_TYPES: &[&str] = &[
+    "text/",
+    "application/json",
+    "application/javascript",
+    "application/xml",
+    "image/svg+xml",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Encoding {
+    Brotli,
+    Zstd,
+    Gzip,
+    Deflate,
+}
+
+impl Encoding {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Encoding::Brotli => "br",
+            Encoding::Zstd => "zstd",
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+        }
+    }
+
+    fn from_token(token: &str) -> Option<Self> {
+        match token.to_ascii_lowercase().as_str() {
+            "br" => Some(Encoding::Brotli),
+            "zstd" => Some(Encoding::Zstd),
+            "gzip" | "x-gzip" => Some(Encoding::Gzip),
+            "deflate" => Some(Encoding::Deflate),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Error)]
+pub(crate) enum DecodeError {
+    #[error("unsupported request content encoding `{0}`")]
+    Unsupported(String),
+    #[error(transparent)]
+    TooLarge(#[from] LengthLimitError),
+}
+
+/// Decodes a request body sent with `Content-Encoding`, so extractors only
+/// ever see the plain bytes. Both the encoded and the decoded body are capped
+/// at `limit` bytes.
+pub(crate) fn decode_body(req: &mut Request<Body>, limit: usize) -> Result<(), DecodeError> {
+    body_limit::limit_body(req, limit)?;
+
+    let mut encodings = vec![];
+    for value in req.headers().get_all(CONTENT_ENCODING) {
+        let value = value.to_str().map_err(|_| {
+            DecodeError::Unsupported(String::from_utf8_lossy(value.as_bytes()).into())
+        })?;
+
+        for token in value.split(',').map(str::trim) {
+            if token.is_empty() || token.eq_ignore_ascii_case("identity") {
+                continue;
+            }
+            let encoding = Encoding::from_token(token)
+                .ok_or_else(|| DecodeError::Unsupported(token.into()))?;
+            encodings.push(encoding);
+        }
+    }
+
+    if encodings.is_empty() {
+        return Ok(());
+    }
+
+    let body = std::mem::replace(req.body_mut(), Body::empty());
+    let mut reader: Pin<Box<dyn AsyncRead + Send>> = Box::pin(StreamReader::new(
+        TryStreamExt::map_err(body, io::Error::other),
+    ));
+
+    // codings are listed in the order they were applied
+    for encoding in encodings.into_iter().rev() {
+        let buffered = BufReader::new(reader);
+        reader = match encoding {
+            Encoding::Brotli => Box::pin(BrotliDecoder::new(buffered)),
+            Encoding::Zstd => Box::pin(ZstdDecoder::new(buffered)),
+            Encoding::Gzip => Box::pin(GzipDecoder::new(buffered)),
+            // HTTP's `deflate` is the zlib format, not raw deflate
+            Encoding::Deflate => Box::pin(ZlibDecoder::new(buffered)),
+        };
+    }
+
+    *req.body_mut() = Body::wrap_stream(ReaderStream::new(reader));
+
+    // the size of the decoded body is unknown up front, limits apply while reading it
+    let headers = req.headers_mut();
+    headers.remove(CONTENT_ENCODING);
+    headers.remove(CONTENT_LENGTH);
+
+    Ok(body_limit::limit_body(req, limit)?)
+}
+
+pub(crate) fn decode_error(err: &DecodeError) -> Response<Body> {
+    match err {
+        DecodeError::TooLarge(err) => body_limit::payload_too_large(err),
+        DecodeError::Unsupported(_) => {
+            tracing::info!("{}", err);
+
+            Response::builder()
+                .status(StatusCode::UNSUPPORTED_MEDIA_TYPE)
+                .header(ACCEPT_ENCODING, "gzip, deflate, br, zstd")
+                .body(Body::empty())
+                .unwrap()
+        }
+    }
+}
+
+/// Compresses responses with the best encoding the client accepts.
+///
+/// Only responses with an allowed content type and at least the minimum size
+/// are compressed. Streaming bodies of unknown length always qualify.
+#[derive(Debug, Clone)]
+pub struct Compression {
+    min_size: u64,
+    content_types: Vec<String>,
+    encodings: Vec<Encoding>,
+}
+
+impl Default for Compression {
+    fn default() -> Self {
+        Compression {
+            min_size: DEFAULT_MIN_SIZE,
+            content_types: DEFAULT_CONTENT_TYPES
+                .iter()
+                .map(|ty| ty.to_string())
+                .collect(),
+            encodings: vec![
+                Encoding::Brotli,
+                Encoding::Zstd,
+                Encoding::Gzip,
+                Encoding::Deflate,
+            ],
+        }
+    }
+}
+
+impl Compression {
+    pub fn new() -> Self {
+        Compression::default()
+    }
+
+    /// Smallest body compressed, 1 KiB by default.
+    pub fn min_size(mut self, size: u64) -> Self {
+        self.min_size = size;
+        self
+    }
+
+    /// Replaces the allowed content types. Entries ending with `/` match a
+    /// whole type, e.g. `text/`.
+    pub fn content_types<I>(mut self, content_types: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<String>,
+    {
+        self.content_types = content_types.into_iter().map(Into::into).collect();
+        self
+    }
+
+    pub fn gzip(self, enable: bool) -> Self {
+        self.toggle(Encoding::Gzip, enable)
+    }
+
+    pub fn deflate(self, enable: bool) -> Self {
+        self.toggle(Encoding::Deflate, enable)
+    }
+
+    pub fn brotli(self, enable: bool) -> Self {
+        self.toggle(Encoding::Brotli, enable)
+    }
+
+    pub fn zstd(self, enable: bool) -> Self {
+        self.toggle(Encoding::Zstd, enable)
+    }
+
+    fn toggle(mut self, encoding: Encoding, enable: bool) -> Self {
+        self.encodings.retain(|enabled| *enabled != encoding);
+        if enable {
+            self.encodings.push(encoding);
+            // keep the preference order
+            self.encodings.sort_by_key(|encoding| *encoding as u8);
+        }
+        self
+    }
+
+    fn compressible(&self, response: &Response<Body>) -> bool {
+        if response.status().is_informational()
+            || response.status() == StatusCode::NO_CONTENT
+            || response.status() == StatusCode::NOT_MODIFIED
+            || response.status() == StatusCode::PARTIAL_CONTENT
+            || response.headers().contains_key(CONTENT_ENCODING)
+        {
+            return false;
+        }
+
+        let content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|ty| ty.to_str().ok())
+            .unwrap_or_default();
+        if content_type.starts_with("text/event-stream") {
+            // encoders buffer their output, which would hold events back
+            return false;
+        }
+
+        let allowed = self.content_types.iter().any(|allowed| {
+            if allowed.ends_with('/') {
+                content_type.starts_with(allowed.as_str())
+            } else {
+                content_type.split(';').next().unwrap_or_default().trim() == allowed
+            }
+        });
+
+        let len = response
+            .headers()
+            .get(CONTENT_LENGTH)
+            .and_then(|len| len.to_str().ok())
+            .and_then(|len| len.parse::<u64>().ok())
+            .or_else(|| response.body().size_hint().exact());
+
+        // bodies of unknown length are compressed
+        allowed && !matches!(len, Some(len) if len < self.min_size)
+    }
+
+    fn negotiate(&self, accept_encoding: &str) -> Option<Encoding> {
+        negotiate(accept_encoding, &self.encodings)
+    }
+
+    /// Compresses `response` for a request that sent `accept_encoding`.
+    pub(crate) fn apply(
+        &self,
+        accept_encoding: Option<&HeaderValue>,
+        response: &mut Response<Body>,
+    ) {
+        if !self.compressible(response) {
+            return;
+        }
+
+        add_vary(response.headers_mut());
+
+        let encoding = match accept_encoding
+            .and_then(|accept| accept.to_str().ok())
+            .and_then(|accept| self.negotiate(accept))
+        {
+            Some(encoding) => encoding,
+            None => return,
+        };
+
+        let body = std::mem::replace(response.body_mut(), Body::empty());
+        let reader = StreamReader::new(TryStreamExt::map_err(body, io::Error::other));
+
+        *response.body_mut() = match encoding {
+            Encoding::Brotli => {
+                // the default quality is too slow for on the fly compression
+                compressed(BrotliEncoder::with_quality(reader, Level::Precise(4)))
+            }
+            Encoding::Zstd => compressed(ZstdEncoder::new(reader)),
+            Encoding::Gzip => compressed(GzipEncoder::new(reader)),
+            Encoding::Deflate => compressed(ZlibEncoder::new(reader)),
+        };
+
+        let headers = response.headers_mut();
+        headers.remove(CONTENT_LENGTH);
+        // ranges of the compressed body are unknown until it is sent
+        headers.remove(ACCEPT_RANGES);
+        // the compressed bytes differ from the ones a strong validator stands for
+        if let Some(etag) = headers.get(ETAG).and_then(|etag| etag.to_str().ok()) {
+            if !etag.starts_with("W/") {
+                let weak = HeaderValue::from_str(&format!("W/{}", etag)).unwrap();
+                headers.insert(ETAG, weak);
+            }
+        }
+        headers.insert(
+            CONTENT_ENCODING,
+            HeaderValue::from_static(encoding.as_str()),
+        );
+    }
+}
+
+/// Picks the encoding of `candidates` with the highest quality in
+/// `accept_encoding`, preferring earlier candidates on ties.
+pub(crate) fn negotiate(accept_encoding: &str, candidates: &[Encoding]) -> Option<Encoding> {
+    let accepted = accept_encoding
+        .split(',')
+        .filter_map(|item| {
+            let mut parts = item.split(';');
+            let coding = parts.next()?.trim().to_ascii_lowercase();
+            let q = parts
+                .filter_map(|param| param.trim().strip_prefix("q="))
+                .find_map(|q| q.parse::<f32>().ok())
+                .unwrap_or(1.0);
+
+            Some((coding, q))
+        })
+        .collect::<Vec<_>>();
+
+    let quality = |coding: &str| {
+        accepted
+            .iter()
+            .find(|(accepted, _)| accepted == coding)
+            .or_else(|| accepted.iter().find(|(accepted, _)| accepted == "*"))
+            .map(|(_, q)| *q)
+    };
+
+    candidates
+        .iter()
+        .filter_map(|encoding| Some((*encoding, quality(encoding.as_str())?)))
+        .filter(|(_, q)| *q > 0.0)
+        .fold(
+            None,
+            |best: Option<(Encoding, f32)>, (encoding, q)| match best {
+                Some((_, best_q)) if best_q >= q => best,
+                _ => Some((encoding, q)),
+            },
+        )
+        .map(|(encoding, _)| encoding)
+}
+
+fn compressed<R: AsyncRead + Send + 'static>(encoder: R) -> Body {
+    Body::wrap_stream(ReaderStream::new(encoder))
+}
+
+fn add_vary(headers: &mut HeaderMap) {
+    let varies = headers
+        .get_all(VARY)
+        .iter()
+        .filter_map(|vary| vary.to_str().ok())
+        .flat_map(|vary| vary.split(','))
+        .any(|vary| vary.trim().eq_ignore_ascii_case(ACCEPT_ENCODING.as_str()));
+
+    if !varies {
+        headers.append(VARY, HeaderValue::from_static("accept-encoding"));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        http::Method,
+        types::{Json, StreamBody},
+        Condey, Route, TestClient,
+    };
+
+    use hyper::body::Bytes;
+    use tokio::io::AsyncReadExt;
+
+    async fn gunzip(body: Bytes) -> String {
+        let mut text = String::new();
+        GzipDecoder::new(&body[..])
+            .read_to_string(&mut text)
+            .await
+            .unwrap();
+        text
+    }
+
+    async fn gzip(data: &[u8]) -> Vec<u8> {
+        let mut compressed = vec![];
+        GzipEncoder::new(data)
+            .read_to_end(&mut compressed)
+            .await
+            .unwrap();
+        compressed
+    }
+
+    #[test]
+    fn negotiate_encoding() {
+        let compression = Compression::new();
+
+        assert_eq!(compression.negotiate("gzip, br"), Some(Encoding::Brotli));
+        assert_eq!(
+            compression.negotiate("gzip;q=1, br;q=0.5"),
+            Some(Encoding::Gzip)
+        );
+        assert_eq!(
+            compression.negotiate("*;q=0.1, br;q=0"),
+            Some(Encoding::Zstd)
+        );
+        assert_eq!(compression.negotiate("identity"), None);
+        assert_eq!(
+            Compression::new().brotli(false).negotiate("br, deflate"),
+            Some(Encoding::Deflate)
+        );
+    }
+
+    #[tokio::test]
+    async fn compress_json() {
+        let route = Route::builder()
+            .method(Method::GET)
+            .path("/albums")
+            .with_handler_fn(|| async { Json(vec!["Crystal Logic"; 100]) });
+        let client = TestClient::new(
+            Condey::init()
+                .mount("", vec![route])
+                .compression(Compression::new()),
+        );
+
+        let response = client
+            .get("/albums")
+            .header("accept-encoding", "gzip")
+            .send()
+            .await;
+        response
+            .assert_header("content-encoding", "gzip")
+            .assert_header("vary", "accept-encoding")
+            .assert_no_header("content-length");
+
+        let expected = serde_json::to_string(&vec!["Crystal Logic"; 100]).unwrap();
+        assert_eq!(gunzip(response.bytes().await).await, expected);
+    }
+
+    #[tokio::test]
+    async fn compress_zlib_deflate() {
+        let route = Route::builder()
+            .method(Method::GET)
+            .path("/albums")
+            .with_handler_fn(|| async { Json(vec!["Crystal Logic"; 100]) });
+        let client = TestClient::new(
+            Condey::init()
+                .mount("", vec![route])
+                .compression(Compression::new()),
+        );
+
+        let response = client
+            .get("/albums")
+            .header("accept-encoding", "deflate")
+            .send()
+            .await;
+        response.assert_header("content-encoding", "deflate");
+
+        let body = response.bytes().await;
+        let mut text = String::new();
+        async_compression::tokio::bufread::ZlibDecoder::new(&body[..])
+            .read_to_string(&mut text)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            text,
+            serde_json::to_string(&vec!["Crystal Logic"; 100]).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn compress_stream() {
+        let route = Route::builder()
+            .method(Method::GET)
+            .path("/stream")
+            .with_handler_fn(|| async {
+                let chunks = (0..10).map(|_| Ok::<_, io::Error>(Bytes::from("a,b\n")));
+                StreamBody::new(futures::stream::iter(chunks)).content_type("text/csv")
+            });
+        let client = TestClient::new(
+            Condey::init()
+                .mount("", vec![route])
+                .compression(Compression::new()),
+        );
+
+        let response = client
+            .get("/stream")
+            .header("accept-encoding", "gzip")
+            .send()
+            .await;
+        response.assert_header("content-encoding", "gzip");
+
+        assert_eq!(gunzip(response.bytes().await).await, "a,b\n".repeat(10));
+    }
+
+    #[tokio::test]
+    async fn skip_compression() {
+        let albums = || async { Json(vec!["Crystal Logic"; 100]) };
+        let routes = vec![
+            Route::builder()
+                .method(Method::GET)
+                .path("/albums")
+                .with_handler_fn(albums),
+            Route::builder()
+                .method(Method::GET)
+                .path("/raw")
+                .with_handler_fn(albums)
+                .compression(false),
+            Route::builder()
+                .method(Method::GET)
+                .path("/small")
+                .with_handler_fn(|| async { Json("tiny") }),
+        ];
+        let client = TestClient::new(
+            Condey::init()
+                .mount("", routes)
+                .compression(Compression::new()),
+        );
+
+        client
+            .get("/albums")
+            .send()
+            .await
+            .assert_no_header("content-encoding")
+            .assert_header("vary", "accept-encoding");
+
+        for path in &["/raw", "/small"] {
+            client
+                .get(path)
+                .header("accept-encoding", "gzip")
+                .send()
+                .await
+                .assert_no_header("content-encoding")
+                .assert_no_header("vary");
+        }
+    }
+
+    #[tokio::test]
+    async fn decode_request_body() {
+        let json = serde_json::to_vec(&vec![7u8; 100]).unwrap();
+        let mut compressed = vec![];
+        BrotliEncoder::new(&gzip(&json).await[..])
+            .read_to_end(&mut compressed)
+            .await
+            .unwrap();
+
+        let route = Route::builder()
+            .method(Method::POST)
+            .path("/echo")
+            .body_limit(1024)
+            .with_handler_fn(
+                |json: Json<Vec<u8>>| async move { json.into_inner().len().to_string() },
+            );
+        let client = TestClient::new(Condey::init().mount("", vec![route]));
+
+        let response = client
+            .post("/echo")
+            .header("content-type", "application/json")
+            .header("content-encoding", "gzip, br")
+            .body(compressed)
+            .send()
+            .await;
+        response.assert_status(StatusCode::OK);
+
+        assert_eq!(response.text().await, "100");
+    }
+
+    #[tokio::test]
+    async fn decode_zlib_deflate() {
+        let json = serde_json::to_vec(&vec![7u8; 100]).unwrap();
+        let mut compressed = vec![];
+        ZlibEncoder::new(&json[..])
+            .read_to_end(&mut compressed)
+            .await
+            .unwrap();
+
+        let route = Route::builder()
+            .method(Method::POST)
+            .path("/echo")
+            .body_limit(1024)
+            .with_handler_fn(
+                |json: Json<Vec<u8>>| async move { json.into_inner().len().to_string() },
+            );
+        let client = TestClient::new(Condey::init().mount("", vec![route]));
+
+        let response = client
+            .post("/echo")
+            .header("content-type", "application/json")
+            .header("content-encoding", "deflate")
+            .body(compressed)
+            .send()
+            .await;
+        response.assert_status(StatusCode::OK);
+
+        assert_eq!(response.text().await, "100");
+    }
+
+    #[tokio::test]
+    async fn limit_encoded_body() {
+        // zlib stream of empty stored blocks, large on the wire but empty once decoded
+        let mut padded = vec![0x78, 0x01];
+        for _ in 0..300 {
+            padded.extend_from_slice(&[0x00, 0x00, 0x00, 0xff, 0xff]);
+        }
+        padded.extend_from_slice(&[0x01, 0x00, 0x00, 0xff, 0xff, 0x00, 0x00, 0x00, 0x01]);
+
+        let route = Route::builder()
+            .method(Method::POST)
+            .path("/echo")
+            .body_limit(1024)
+            .with_handler_fn(
+                |json: Json<Vec<u8>>| async move { json.into_inner().len().to_string() },
+            );
+        let client = TestClient::new(Condey::init().mount("", vec![route]));
+
+        client
+            .post("/echo")
+            .header("content-encoding", "deflate")
+            .header("content-length", padded.len().to_string())
+            .body(padded.clone())
+            .send()
+            .await
+            .assert_status(StatusCode::PAYLOAD_TOO_LARGE);
+        client
+            .post("/echo")
+            .header("content-encoding", "deflate")
+            .body(padded)
+            .send()
+            .await
+            .assert_status(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+
+    #[tokio::test]
+    async fn reject_request_encoding() {
+        let route = Route::builder()
+            .method(Method::POST)
+            .path("/echo")
+            .body_limit(1024)
+            .with_handler_fn(
+                |json: Json<Vec<u8>>| async move { json.into_inner().len().to_string() },
+            );
+        let client = TestClient::new(Condey::init().mount("", vec![route]));
+
+        client
+            .post("/echo")
+            .header("content-encoding", "compress")
+            .body("[]")
+            .send()
+            .await
+            .assert_status(StatusCode::UNSUPPORTED_MEDIA_TYPE);
+
+        let bomb = serde_json::to_vec(&vec![0u8; 10_000]).unwrap();
+        client
+            .post("/echo")
+            .header("content-type", "application/json")
+            .header("content-encoding", "gzip")
+            .body(gzip(&bomb).await)
+            .send()
+            .await
+            .assert_status(StatusCode::PAYLOAD_TOO_LARGE);
+    }
+}
@@ -1,23 +1,47 @@
-use super::{handler::Handler, route::Route};
+#[cfg(unix)]
+use super::unix::UnixSocket;
+use super::{
+    compression::{self, Compression},
+    config::ServerConfig,
+    connection::{ConnectInfo, Connected},
+    cors::{self, Cors},
+    limits::{LimitedConn, LimitedIncoming, TrackedBody},
+    listener::Listener,
+    proxy::ClientAddr,
+    range::RangeRequest,
+    route::Route,
+    route_table::{RouteTable, Routers, RoutesHandle},
+    service::{self, AppService, BoxedLayer},
+    shutdown::{self, ConnectionCounter, ShutdownSignal},
+    tls::TlsConfig,
+};
 use crate::{
     http::{Method, Request, Response},
+    types::{PendingUpgrade, Preconditions},
     Body,
 };
 
 use fnv::FnvHashMap as HashMap;
+use futures::{future, Future, FutureExt, TryFutureExt};
 use hyper::{
-    header::SERVER,
+    body::HttpBody,
+    header::{
+        ACCEPT_ENCODING, ACCESS_CONTROL_REQUEST_METHOD, ALLOW, CONTENT_LENGTH, ORIGIN, SERVER,
+    },
     http::HeaderValue,
-    server::conn::AddrIncoming,
+    server::accept::Accept,
     service::{make_service_fn, service_fn},
+    upgrade::OnUpgrade,
     Server, StatusCode,
 };
-use route_recognizer::Router;
 use thiserror::Error;
 use tokio::{
-    net::{lookup_host, ToSocketAddrs},
+    io::{AsyncRead, AsyncWrite},
+    net::ToSocketAddrs,
+    sync::Semaphore,
     time::Instant,
 };
+use tower::{util::BoxCloneService, BoxError, Layer, Service, ServiceExt};
 use tracing_futures::Instrument;
 
 use std::{
@@ -26,6 +50,7 @@ use std::{
     sync::Arc,
 };
 
+#[allow(clippy::enum_variant_names)]
 #[derive(Debug, Error)]
 pub enum ServerError {
     #[error("Mount paths are corruputed")]
@@ -37,49 +62,100 @@ pub enum ServerError {
     #[error("Socket address not resolved")]
     NotResolvedError,
 
+    #[error("No listener to serve on")]
+    NoListenerError,
+
     #[error("Runtime error: {0}")]
     RuntimeError(#[from] hyper::Error),
+
+    #[error("Invalid certificate: {0}")]
+    CertificateError(String),
+
+    #[error("Invalid private key: {0}")]
+    PrivateKeyError(String),
+
+    #[error("TLS error: {0}")]
+    TlsError(#[from] tokio_rustls::rustls::Error),
+
+    #[error("Socket activation failed: {0}")]
+    SocketActivationError(String),
+
+    #[error("Drain timed out, dropped {connections} open connection(s)")]
+    DrainTimeoutError { connections: usize },
 }
 
-fn request_span(method: &Method, path: &str) -> tracing::Span {
+const MIN_HTTP1_BUF_SIZE: usize = 8192;
+
+fn request_span(req: &Request<Body>, path: &str) -> tracing::Span {
     let span = tracing::info_span!(
         "request",
-        method = ?method,
-        path = ?path
+        method = ?req.method(),
+        path = ?path,
+        client = tracing::field::Empty,
+        remote = tracing::field::Empty,
+        local = tracing::field::Empty,
     );
+    if let Some(client) = req.extensions().get::<ClientAddr>() {
+        span.record("client", tracing::field::display(client));
+    }
+    if let Some(info) = req.extensions().get::<ConnectInfo>() {
+        if let Some(remote) = info.remote_addr() {
+            span.record("remote", tracing::field::display(remote));
+        }
+        span.record("local", tracing::field::display(info.local_addr()));
+    }
     tracing::info!(parent: &span, "received request");
     span
 }
 
 pub struct Condey {
-    routes: Vec<Route>,
+    routes: RouteTable,
+    routes_handle: RoutesHandle,
     states: HashMap<TypeId, Box<dyn Any + Send + Sync + 'static>>,
+    config: ServerConfig,
+    shutdown: Option<ShutdownSignal>,
+    layers: Vec<BoxedLayer>,
+    cors: Option<Cors>,
+    compression: Option<Compression>,
 }
 
 impl Condey {
     pub fn init() -> Self {
         Condey {
-            routes: vec![],
+            routes: RouteTable::new(),
+            routes_handle: RoutesHandle::default(),
             states: HashMap::default(),
+            config: ServerConfig::default(),
+            shutdown: None,
+            layers: vec![],
+            cors: None,
+            compression: None,
         }
     }
 
-    pub fn mount(mut self, prefix: &str, paths: Vec<Route>) -> Self {
-        paths.into_iter().for_each(|mut route| {
-            route.path = format!("{}/{}", prefix, route.path.trim_start_matches('/'));
+    pub fn mount(mut self, prefix: &str, paths: impl Into<Vec<Route>>) -> Self {
+        self.routes = self.routes.mount(prefix, paths);
 
-            if route.method == Method::GET {
-                let mut route_head = route.clone();
-                route_head.method = Method::HEAD;
-                self.routes.push(route_head);
-            }
+        self
+    }
 
-            self.routes.push(route);
-        });
+    /// Mounts `paths` with a CORS policy for routes that have none of their own.
+    pub fn mount_with_cors(
+        mut self,
+        prefix: &str,
+        paths: impl Into<Vec<Route>>,
+        cors: Cors,
+    ) -> Self {
+        self.routes = self.routes.mount_with_cors(prefix, paths, cors);
 
         self
     }
 
+    /// Handle replacing the mounted routes while the app is running.
+    pub fn routes_handle(&self) -> RoutesHandle {
+        self.routes_handle.clone()
+    }
+
     pub fn app_state<T: Any + Clone + Send + Sync + 'static>(mut self, state: T) -> Self {
         tracing::debug!("Registering state of type {}", std::any::type_name::<T>());
         let type_id = state.type_id();
@@ -89,77 +165,320 @@ impl Condey {
         self
     }
 
+    /// CORS policy of routes and mounts without their own.
+    pub fn cors(mut self, cors: Cors) -> Self {
+        self.cors = Some(cors);
+
+        self
+    }
+
+    /// Compresses responses of all routes that don't opt out.
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = Some(compression);
+
+        self
+    }
+
+    /// Wraps the whole app in a tower layer. Layers added later wrap the ones added before.
+    pub fn layer<L>(mut self, layer: L) -> Self
+    where
+        L: Layer<BoxCloneService<Request<Body>, Response<Body>, BoxError>> + Send + 'static,
+        L::Service: Service<Request<Body>, Response = Response<Body>> + Clone + Send + 'static,
+        <L::Service as Service<Request<Body>>>::Error: Into<BoxError>,
+        <L::Service as Service<Request<Body>>>::Future: Send + 'static,
+    {
+        self.layers.push(service::boxed_layer(layer));
+
+        self
+    }
+
+    pub fn server_config(mut self, config: ServerConfig) -> Self {
+        self.config = config;
+
+        self
+    }
+
+    /// Stops accepting new connections once `signal` resolves and lets in-flight
+    /// requests finish within [`ServerConfig::drain_timeout`].
+    pub fn graceful_shutdown<F>(mut self, signal: F) -> Self
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        self.shutdown = Some(signal.boxed());
+
+        self
+    }
+
     pub async fn listen_at(self, addr: impl ToSocketAddrs) -> Result<(), ServerError> {
-        let addr = lookup_host(addr)
-            .await?
-            .next()
-            .ok_or(ServerError::NotResolvedError)?;
+        let listener = Listener::bind(addr).await?;
+
+        self.listen_on(listener).await
+    }
 
+    /// Serves HTTPS on `addr`, terminating TLS with the given certificate and key.
+    pub async fn listen_tls_at(
+        self,
+        addr: impl ToSocketAddrs,
+        tls: TlsConfig,
+    ) -> Result<(), ServerError> {
+        let listener = Listener::bind(addr).await?.tls(tls);
+
+        self.listen_on(listener).await
+    }
+
+    /// Serves on a Unix domain socket. Handlers can identify the peer with [`UnixPeer`](crate::UnixPeer).
+    #[cfg(unix)]
+    pub async fn listen_unix(self, socket: UnixSocket) -> Result<(), ServerError> {
+        let listener = Listener::unix(&socket)?;
+
+        self.listen_on(listener).await
+    }
+
+    /// Builds the app into a `tower::Service` without serving it.
+    pub fn into_service(mut self) -> Result<AppService, ServerError> {
+        let layers = std::mem::take(&mut self.layers);
         let condey_service = CondeyService::try_from(self)?;
-        let condey_service = Arc::new(condey_service);
 
-        let make_svc = make_service_fn(move |_conn| {
-            let condey = condey_service.clone();
+        Ok(AppService::new(Arc::new(condey_service), layers))
+    }
 
-            async move {
-                // service_fn converts our function into a `Service`
-                Ok::<_, Infallible>(service_fn(move |req| condey.clone().handle_request(req)))
-            }
-        });
+    /// Serves on an already bound listener, e.g. one inherited through systemd
+    /// socket activation or bound to port 0 beforehand.
+    pub async fn listen_on(self, listener: impl Into<Listener>) -> Result<(), ServerError> {
+        self.listen_on_all(vec![listener.into()]).await
+    }
 
-        let mut incoming = AddrIncoming::bind(&addr)?;
-        incoming.set_nodelay(true);
+    /// Serves the same routes and state on every listener. All listeners stop
+    /// accepting on the same shutdown signal and share one drain deadline.
+    pub async fn listen_on_all(
+        mut self,
+        listeners: impl IntoIterator<Item = Listener>,
+    ) -> Result<(), ServerError> {
+        let listeners = listeners.into_iter().collect::<Vec<_>>();
+        if listeners.is_empty() {
+            return Err(ServerError::NoListenerError);
+        }
+
+        let config = self.config.clone();
+        let signal = self
+            .shutdown
+            .take()
+            .unwrap_or_else(|| future::pending().boxed());
+
+        let app = self.into_service()?;
+        let connections = ConnectionCounter::default();
+
+        let permits = config
+            .max_connections
+            .map(|max| Arc::new(Semaphore::new(max)));
+
+        let (signal, signalled) = shutdown::observe(signal);
+        let signal = signal.shared();
+
+        let servers = listeners.into_iter().map(|listener| {
+            listener.serve(
+                app.clone(),
+                &config,
+                connections.clone(),
+                permits.clone(),
+                signal.clone(),
+            )
+        });
+        let server = future::try_join_all(servers).map_ok(|_| ());
 
-        let server = Server::builder(incoming)
-            .http1_pipeline_flush(true)
-            .serve(make_svc);
+        shutdown::run_until_drained(server, signalled, config.drain_timeout, connections).await
+    }
+}
 
-        server.await.map(|_| ()).map_err(ServerError::RuntimeError)
+pub(crate) async fn serve_incoming<I, F>(
+    incoming: I,
+    app: AppService,
+    config: &ServerConfig,
+    connections: ConnectionCounter,
+    permits: Option<Arc<Semaphore>>,
+    signal: F,
+) -> Result<(), ServerError>
+where
+    I: Accept + Unpin,
+    I::Conn: AsyncRead + AsyncWrite + Connected + Unpin + Send + 'static,
+    I::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+    F: Future<Output = ()>,
+{
+    let incoming = LimitedIncoming::new(incoming, config, permits);
+    let executor = connections.clone();
+
+    let make_svc = make_service_fn(move |conn: &LimitedConn<I::Conn>| {
+        let app = app.clone();
+        let guard = connections.track();
+        let upgrades = connections.clone();
+        let info = conn.connect_info();
+        let requests = conn.request_tracker();
+        let span = conn.span().clone();
+
+        async move {
+            // service_fn converts our function into a `Service`
+            Ok::<_, Infallible>(service_fn(move |mut req: Request<Body>| {
+                let _ = &guard;
+                let requests = requests.clone();
+                let request_guard = requests.start();
+                I::Conn::insert_info(&info, req.extensions_mut());
+                req.extensions_mut().insert(upgrades.clone());
+
+                app.clone()
+                    .oneshot(req)
+                    .map_ok(move |response| {
+                        if response.status() == StatusCode::SWITCHING_PROTOCOLS {
+                            requests.upgrade();
+                        }
+                        response.map(|body| TrackedBody::new(body, request_guard))
+                    })
+                    .instrument(span.clone())
+            }))
+        }
+    });
+
+    let mut builder = Server::builder(incoming)
+        .executor(executor)
+        .http1_pipeline_flush(true)
+        .http2_max_concurrent_streams(config.http2_max_concurrent_streams)
+        .http2_initial_stream_window_size(config.http2_initial_stream_window_size)
+        .http2_initial_connection_window_size(config.http2_initial_connection_window_size);
+
+    if let Some(size) = config.max_header_size {
+        builder = builder
+            .http1_max_buf_size(size.max(MIN_HTTP1_BUF_SIZE))
+            .http2_max_header_list_size(u32::try_from(size).unwrap_or(u32::MAX));
     }
+
+    builder
+        .serve(make_svc)
+        .with_graceful_shutdown(signal)
+        .await
+        .map_err(ServerError::RuntimeError)
 }
 
 pub type StateMap = Arc<HashMap<TypeId, Box<dyn Any + Send + Sync + 'static>>>;
 
-struct CondeyService {
-    routes: HashMap<Method, Router<Arc<dyn Handler>>>,
+pub(crate) struct CondeyService {
+    routes: RoutesHandle,
     states: StateMap,
+    body_limit: usize,
+    cors: Option<Arc<Cors>>,
+    compression: Option<Compression>,
 }
 
 impl CondeyService {
-    async fn handle_request(
+    pub(crate) async fn handle_request(
         self: Arc<Self>,
         mut req: Request<Body>,
     ) -> Result<Response<Body>, Infallible> {
         let timer = Instant::now();
         let path = req.uri().path().trim_end_matches('/').to_string();
-        let method = req.method();
-
-        let span = request_span(method, &path);
+        let span = request_span(&req, &path);
         let _ = span.enter();
 
         req.extensions_mut().insert(Arc::clone(&self.states));
 
-        let mut response = match self
-            .routes
-            .get(req.method())
-            .and_then(|node| node.recognize(&path).ok())
-        {
+        if let Some(on_upgrade) = req.extensions_mut().remove::<OnUpgrade>() {
+            req.extensions_mut().insert(PendingUpgrade::new(on_upgrade));
+        }
+
+        let routes = self.routes.current();
+        let is_head = req.method() == Method::HEAD;
+
+        if cors::is_preflight(&req) {
+            if let Some(response) = self.preflight(&routes, &req, &path) {
+                return Ok(self.finish(response, timer));
+            }
+        }
+
+        let origin = req.headers().get(ORIGIN).cloned();
+        let accept_encoding = req.headers().get(ACCEPT_ENCODING).cloned();
+        let is_safe = is_head || req.method() == Method::GET;
+        let preconditions = Preconditions::from_headers(req.method(), req.headers());
+        let range = RangeRequest::from_headers(req.method(), req.headers());
+        let mut cors = self.cors.clone();
+        let mut compress = self.compression.is_some();
+
+        let mut response = match routes.recognize(req.method(), &path) {
             Some(lookup) => {
                 let params = lookup.params();
-                let handler = lookup.handler();
+                let route = lookup.handler();
+                if route.cors.is_some() {
+                    cors = route.cors.clone();
+                }
+                compress = route.compression.unwrap_or(compress);
 
                 req.extensions_mut().insert(params.clone());
-                match handler.handle_request(req).instrument(span).await {
-                    Ok(resp) => resp,
-                    Err(()) => Response::builder()
-                        .status(StatusCode::INTERNAL_SERVER_ERROR)
-                        .body(Body::empty())
-                        .unwrap(),
+                let limit = route.body_limit.unwrap_or(self.body_limit);
+
+                let rejected = compression::decode_body(&mut req, limit)
+                    .err()
+                    .map(|err| compression::decode_error(&err));
+
+                match rejected {
+                    None => match route.handler.handle_request(req).instrument(span).await {
+                        Ok(resp) => resp,
+                        Err(()) => Response::builder()
+                            .status(StatusCode::INTERNAL_SERVER_ERROR)
+                            .body(Body::empty())
+                            .unwrap(),
+                    },
+                    Some(resp) => resp,
                 }
             }
-            None => self.not_found_or_method_not_allowed(&path),
+            None => unmatched_method(&routes, req.method(), &path),
         };
 
+        // other methods have to check preconditions before they change anything
+        if is_safe {
+            preconditions.apply(&mut response);
+            range.apply(&mut response).await;
+        }
+
+        if compress {
+            let default = Compression::default();
+            let compression = self.compression.as_ref().unwrap_or(&default);
+            compression.apply(accept_encoding.as_ref(), &mut response);
+        }
+
+        if is_head {
+            strip_body(&mut response);
+        }
+
+        if let Some(cors) = cors {
+            cors.apply(origin.as_ref(), &mut response);
+        }
+
+        Ok(self.finish(response, timer))
+    }
+
+    /// Answers a CORS preflight with the policy of the route it asks for,
+    /// `None` when no policy applies.
+    fn preflight(
+        &self,
+        routes: &Routers,
+        req: &Request<Body>,
+        path: &str,
+    ) -> Option<Response<Body>> {
+        let registered = routes.allowed_methods(path);
+        if registered.is_empty() {
+            return None;
+        }
+
+        let route_cors = req
+            .headers()
+            .get(ACCESS_CONTROL_REQUEST_METHOD)
+            .and_then(|method| Method::from_bytes(method.as_bytes()).ok())
+            .and_then(|method| routes.recognize(&method, path))
+            .and_then(|lookup| lookup.handler().cors.clone());
+
+        let cors = route_cors.or_else(|| self.cors.clone())?;
+
+        Some(cors.preflight(req, &registered))
+    }
+
+    fn finish(&self, mut response: Response<Body>, timer: Instant) -> Response<Body> {
         response.headers_mut().insert(
             SERVER,
             HeaderValue::try_from(format!("condey {}", env!("CARGO_PKG_VERSION"))).unwrap(),
@@ -171,54 +490,117 @@ impl CondeyService {
             timer.elapsed()
         );
 
-        Ok(response)
+        response
     }
+}
 
-    fn not_found_or_method_not_allowed(self: Arc<Self>, path: &str) -> Response<Body> {
-        let status = if self
-            .routes
-            .iter()
-            .filter(|(_, router)| router.recognize(path).ok().is_some())
-            .count()
-            != 0
-        {
-            StatusCode::METHOD_NOT_ALLOWED
-        } else {
-            StatusCode::NOT_FOUND
-        };
+/// Drops the body of a HEAD response, keeping the length the GET response would have.
+fn strip_body(response: &mut Response<Body>) {
+    let body = std::mem::replace(response.body_mut(), Body::empty());
 
-        Response::builder()
-            .status(status)
+    if !response.headers().contains_key(CONTENT_LENGTH) {
+        if let Some(len) = body.size_hint().exact() {
+            response
+                .headers_mut()
+                .insert(CONTENT_LENGTH, HeaderValue::from(len));
+        }
+    }
+}
+
+/// Answers OPTIONS and requests with a method no route of the path handles.
+fn unmatched_method(routes: &Routers, method: &Method, path: &str) -> Response<Body> {
+    let allowed = routes.allowed_methods(path);
+    if allowed.is_empty() {
+        return Response::builder()
+            .status(StatusCode::NOT_FOUND)
             .body(Body::empty())
-            .unwrap()
+            .unwrap();
     }
+
+    let status = if method == Method::OPTIONS {
+        StatusCode::NO_CONTENT
+    } else {
+        StatusCode::METHOD_NOT_ALLOWED
+    };
+
+    let allow = allowed
+        .iter()
+        .map(Method::as_str)
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    Response::builder()
+        .status(status)
+        .header(ALLOW, allow)
+        .body(Body::empty())
+        .unwrap()
 }
 
 impl TryFrom<Condey> for CondeyService {
     type Error = ServerError;
 
     fn try_from(condey: Condey) -> Result<Self, Self::Error> {
-        // TODO: matchit should provide some Result<T,E> API
-        let routes_unchecked = condey.routes;
-        let routes = {
-            let mut routes: HashMap<_, Router<_>> = HashMap::default();
-
-            routes_unchecked.into_iter().for_each(|route| {
-                let path = route.path;
-                let method = route.method;
-                let handler = route.handler;
-                tracing::info!("mounting route: {} {}", method, path);
-
-                let node = routes.entry(method).or_default();
-                node.add(&path, handler);
-            });
-
-            routes
-        };
+        condey.routes_handle.install(condey.routes.compile());
 
         Ok(Self {
-            routes,
+            routes: condey.routes_handle,
             states: Arc::new(condey.states),
+            body_limit: condey.config.body_limit,
+            cors: condey.cors.map(Arc::new),
+            compression: condey.compression,
         })
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use hyper::{http::Version, Client};
+
+    #[tokio::test]
+    async fn serve_h2c_prior_knowledge() {
+        let routes = vec![Route::builder()
+            .method(Method::GET)
+            .path("/hello")
+            .with_handler_fn(|| async { "Hello, h2c!".to_string() })];
+
+        let listener = Listener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().tcp().unwrap();
+        tokio::spawn(Condey::init().mount("", routes).listen_on(listener));
+
+        let client = Client::builder().http2_only(true).build_http::<Body>();
+        let response = client
+            .get(format!("http://{}/hello", addr).parse().unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.version(), Version::HTTP_2);
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(&body[..], b"Hello, h2c!");
+    }
+
+    #[tokio::test]
+    async fn head_keeps_content_length() {
+        let listener = Listener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().tcp().unwrap();
+
+        let routes = vec![Route::builder()
+            .method(Method::GET)
+            .path("/hello")
+            .with_handler_fn(|| async { "Hello!".to_string() })];
+        tokio::spawn(Condey::init().mount("", routes).listen_on(listener));
+
+        let request = hyper::Request::head(format!("http://{}/hello", addr))
+            .body(Body::empty())
+            .unwrap();
+        let response = Client::new().request(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(response.headers()[CONTENT_LENGTH], "6");
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert!(body.is_empty());
+    }
+}
@@ -0,0 +1,96 @@
+use std::time::Duration;
+
+const DEFAULT_DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+const DEFAULT_BODY_LIMIT: usize = 2 * 1024 * 1024;
+
+/// Server-level settings applied by [`Condey`](crate::Condey) when it starts listening.
+///
+/// HTTP/2 is negotiated through ALPN on TLS listeners and detected through the
+/// connection preface (prior knowledge) on cleartext ones. `Upgrade: h2c` requests
+/// are served as HTTP/1.1.
+#[derive(Debug, Clone)]
+pub struct ServerConfig {
+    pub(crate) drain_timeout: Duration,
+    pub(crate) max_connections: Option<usize>,
+    pub(crate) header_read_timeout: Option<Duration>,
+    pub(crate) keep_alive_timeout: Option<Duration>,
+    pub(crate) max_header_size: Option<usize>,
+    pub(crate) body_limit: usize,
+    pub(crate) http2_max_concurrent_streams: Option<u32>,
+    pub(crate) http2_initial_stream_window_size: Option<u32>,
+    pub(crate) http2_initial_connection_window_size: Option<u32>,
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        ServerConfig {
+            drain_timeout: DEFAULT_DRAIN_TIMEOUT,
+            max_connections: None,
+            header_read_timeout: None,
+            keep_alive_timeout: None,
+            max_header_size: None,
+            body_limit: DEFAULT_BODY_LIMIT,
+            http2_max_concurrent_streams: None,
+            http2_initial_stream_window_size: None,
+            http2_initial_connection_window_size: None,
+        }
+    }
+}
+
+impl ServerConfig {
+    /// Maximum time to wait for open connections to finish after the shutdown signal fired.
+    pub fn drain_timeout(mut self, timeout: Duration) -> Self {
+        self.drain_timeout = timeout;
+        self
+    }
+
+    /// Maximum number of open connections across all listeners. Connections
+    /// accepted above the limit are closed right away.
+    pub fn max_connections(mut self, max: usize) -> Self {
+        self.max_connections = Some(max);
+        self
+    }
+
+    /// Time a client gets to send the complete head of a request, counted from
+    /// accepting the connection or from the first byte of a keep-alive request.
+    pub fn header_read_timeout(mut self, timeout: Duration) -> Self {
+        self.header_read_timeout = Some(timeout);
+        self
+    }
+
+    /// Time a keep-alive connection may stay idle between requests.
+    pub fn keep_alive_timeout(mut self, timeout: Duration) -> Self {
+        self.keep_alive_timeout = Some(timeout);
+        self
+    }
+
+    /// Maximum size of request headers in bytes. HTTP/1 needs at least 8 KiB,
+    /// smaller values are rounded up for it.
+    pub fn max_header_size(mut self, size: usize) -> Self {
+        self.max_header_size = Some(size);
+        self
+    }
+
+    /// Maximum size of request bodies in bytes, 2 MiB by default. Larger
+    /// requests get `413 Payload Too Large`. Compressed bodies are limited by
+    /// their decoded size. Routes can override it with `RouteBuilder::body_limit`.
+    pub fn body_limit(mut self, limit: usize) -> Self {
+        self.body_limit = limit;
+        self
+    }
+
+    pub fn http2_max_concurrent_streams(mut self, max: u32) -> Self {
+        self.http2_max_concurrent_streams = Some(max);
+        self
+    }
+
+    pub fn http2_initial_stream_window_size(mut self, size: u32) -> Self {
+        self.http2_initial_stream_window_size = Some(size);
+        self
+    }
+
+    pub fn http2_initial_connection_window_size(mut self, size: u32) -> Self {
+        self.http2_initial_connection_window_size = Some(size);
+        self
+    }
+}
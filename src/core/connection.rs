@@ -0,0 +1,149 @@
+use super::{from_request::FromRequest, listener::ListenAddr, proxy::ClientAddr, tls::TlsInfo};
+use crate::{http::Extensions, Interceptor, Request};
+
+use anyhow::anyhow;
+use hyper::{server::conn::AddrStream, StatusCode};
+
+use std::net::SocketAddr;
+
+/// Per-connection details handed to every request served over that connection.
+pub(crate) trait Connected {
+    type Info: Clone + Send + Sync + Unpin + 'static;
+
+    fn connect_info(&self) -> Self::Info;
+
+    /// Address of the peer, `None` for local sockets.
+    fn remote_addr(&self) -> Option<SocketAddr> {
+        None
+    }
+
+    /// Exposes the info to extractors through request extensions.
+    fn insert_info(info: &Self::Info, extensions: &mut Extensions) {
+        extensions.insert(info.clone());
+    }
+}
+
+/// Details of the connection a request was received on.
+///
+/// The remote address is the socket peer, which is the proxy for connections
+/// using the PROXY protocol; see [`ClientAddr`] for the client behind it.
+#[derive(Debug, Clone)]
+pub struct ConnectInfo {
+    remote: Option<SocketAddr>,
+    local: ListenAddr,
+    tls: Option<TlsInfo>,
+}
+
+impl ConnectInfo {
+    pub(crate) fn new(remote: Option<SocketAddr>, local: ListenAddr) -> Self {
+        ConnectInfo {
+            remote,
+            local,
+            tls: None,
+        }
+    }
+
+    /// Address of the peer, `None` for Unix sockets.
+    pub fn remote_addr(&self) -> Option<SocketAddr> {
+        self.remote
+    }
+
+    /// Address the connection was accepted on.
+    pub fn local_addr(&self) -> &ListenAddr {
+        &self.local
+    }
+
+    /// Negotiated TLS parameters, `None` for cleartext connections.
+    pub fn tls(&self) -> Option<&TlsInfo> {
+        self.tls.as_ref()
+    }
+
+    pub(crate) fn set_tls(&mut self, tls: TlsInfo) {
+        self.tls = Some(tls);
+    }
+}
+
+#[async_trait::async_trait]
+impl<'r> FromRequest<'r> for ConnectInfo {
+    type Error = anyhow::Error;
+
+    async fn from_request(request: &'r Request) -> Result<Self, Self::Error> {
+        request
+            .extensions()
+            .get::<ConnectInfo>()
+            .cloned()
+            .ok_or_else(|| anyhow!("request was not received over a connection"))
+    }
+
+    fn default_interceptor() -> Box<dyn Interceptor> {
+        Box::new(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+}
+
+impl Connected for AddrStream {
+    type Info = ConnectInfo;
+
+    fn connect_info(&self) -> Self::Info {
+        ConnectInfo::new(
+            Some(AddrStream::remote_addr(self)),
+            ListenAddr::Tcp(self.local_addr()),
+        )
+    }
+
+    fn remote_addr(&self) -> Option<SocketAddr> {
+        Some(AddrStream::remote_addr(self))
+    }
+
+    fn insert_info(info: &Self::Info, extensions: &mut Extensions) {
+        if let Some(remote) = info.remote {
+            extensions.insert(ClientAddr::direct(remote));
+        }
+        extensions.insert(info.clone());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{http::Method, Condey, Listener, Route, TestClient};
+
+    use hyper::Client;
+
+    fn routes() -> Vec<Route> {
+        vec![Route::builder()
+            .method(Method::GET)
+            .path("/connection")
+            .with_handler_fn(|info: ConnectInfo| async move {
+                format!("{:?} {}", info.remote_addr(), info.local_addr())
+            })]
+    }
+
+    #[tokio::test]
+    async fn tcp_connect_info() {
+        let listener = Listener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().tcp().unwrap();
+
+        tokio::spawn(Condey::init().mount("", routes()).listen_on(listener));
+
+        let response = Client::new()
+            .get(format!("http://{}/connection", addr).parse().unwrap())
+            .await
+            .unwrap();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        let body = String::from_utf8(body.to_vec()).unwrap();
+
+        assert!(body.starts_with("Some(127.0.0.1:"));
+        assert!(body.ends_with(&format!(" {}", addr)));
+    }
+
+    #[tokio::test]
+    async fn missing_connect_info() {
+        let client = TestClient::new(Condey::init().mount("", routes()));
+
+        client
+            .get("/connection")
+            .send()
+            .await
+            .assert_status(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+}
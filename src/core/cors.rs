@@ -0,0 +1,383 @@
+use crate::{
+    http::{
+        header::{
+            HeaderName, ACCESS_CONTROL_ALLOW_CREDENTIALS, ACCESS_CONTROL_ALLOW_HEADERS,
+            ACCESS_CONTROL_ALLOW_METHODS, ACCESS_CONTROL_ALLOW_ORIGIN,
+            ACCESS_CONTROL_EXPOSE_HEADERS, ACCESS_CONTROL_MAX_AGE, ACCESS_CONTROL_REQUEST_HEADERS,
+            ACCESS_CONTROL_REQUEST_METHOD, ORIGIN, VARY,
+        },
+        HeaderMap, HeaderValue, Method, Request, Response, StatusCode,
+    },
+    Body,
+};
+
+use std::{fmt, sync::Arc, time::Duration};
+
+const ANY_ORIGIN_WITH_CREDENTIALS: &str =
+    "invalid CORS policy: credentials cannot be allowed for any origin";
+
+#[derive(Clone)]
+enum AllowOrigin {
+    Exact(String),
+    /// `*` in the middle of the pattern, e.g. `https://*.example.com`.
+    Wildcard(String, String),
+    Predicate(Arc<dyn Fn(&str) -> bool + Send + Sync>),
+}
+
+impl AllowOrigin {
+    fn matches(&self, origin: &str) -> bool {
+        match self {
+            AllowOrigin::Exact(exact) => exact == origin,
+            AllowOrigin::Wildcard(prefix, suffix) => {
+                origin.len() > prefix.len() + suffix.len()
+                    && origin.starts_with(prefix.as_str())
+                    && origin.ends_with(suffix.as_str())
+            }
+            AllowOrigin::Predicate(predicate) => predicate(origin),
+        }
+    }
+}
+
+/// Cross-origin resource sharing policy, applied to the whole app, a mount or
+/// a single route. The most specific policy wins.
+///
+/// Preflight requests are answered without calling handlers. Disallowed
+/// preflights get `403 Forbidden`, other requests from disallowed origins are
+/// served without CORS headers.
+#[derive(Clone, Default)]
+pub struct Cors {
+    any_origin: bool,
+    origins: Vec<AllowOrigin>,
+    methods: Option<Vec<Method>>,
+    any_header: bool,
+    headers: Vec<HeaderName>,
+    expose_headers: Vec<HeaderName>,
+    credentials: bool,
+    max_age: Option<Duration>,
+}
+
+impl fmt::Debug for Cors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Cors")
+            .field("any_origin", &self.any_origin)
+            .field("origins", &self.origins.len())
+            .field("methods", &self.methods)
+            .field("headers", &self.headers)
+            .field("credentials", &self.credentials)
+            .finish()
+    }
+}
+
+impl Cors {
+    pub fn new() -> Self {
+        Cors::default()
+    }
+
+    /// Allows an exact origin, `*` for any origin, or a pattern with one `*`
+    /// like `https://*.example.com`.
+    ///
+    /// # Panics
+    ///
+    /// When allowing any origin on a policy that allows credentials.
+    pub fn allow_origin(mut self, origin: &str) -> Self {
+        match origin.split_once('*') {
+            Some(("", "")) => {
+                assert!(!self.credentials, "{}", ANY_ORIGIN_WITH_CREDENTIALS);
+                self.any_origin = true
+            }
+            Some((prefix, suffix)) => self.origins.push(AllowOrigin::Wildcard(
+                prefix.to_string(),
+                suffix.to_string(),
+            )),
+            None => self.origins.push(AllowOrigin::Exact(origin.to_string())),
+        }
+        self
+    }
+
+    pub fn allow_origin_fn<F>(mut self, predicate: F) -> Self
+    where
+        F: Fn(&str) -> bool + Send + Sync + 'static,
+    {
+        self.origins
+            .push(AllowOrigin::Predicate(Arc::new(predicate)));
+        self
+    }
+
+    /// Methods allowed in preflights. Defaults to the methods registered for the path.
+    pub fn allow_methods(mut self, methods: impl IntoIterator<Item = Method>) -> Self {
+        self.methods = Some(methods.into_iter().collect());
+        self
+    }
+
+    pub fn allow_headers(mut self, headers: impl IntoIterator<Item = HeaderName>) -> Self {
+        self.headers.extend(headers);
+        self
+    }
+
+    /// Allows whatever headers a preflight asks for.
+    pub fn allow_any_header(mut self) -> Self {
+        self.any_header = true;
+        self
+    }
+
+    pub fn expose_headers(mut self, headers: impl IntoIterator<Item = HeaderName>) -> Self {
+        self.expose_headers.extend(headers);
+        self
+    }
+
+    /// Allows cookies and credentials.
+    ///
+    /// # Panics
+    ///
+    /// When the policy allows any origin, browsers reject `*` with credentials.
+    pub fn allow_credentials(mut self, allow: bool) -> Self {
+        assert!(
+            !(allow && self.any_origin),
+            "{}",
+            ANY_ORIGIN_WITH_CREDENTIALS
+        );
+        self.credentials = allow;
+        self
+    }
+
+    /// Time browsers may cache preflight results.
+    pub fn max_age(mut self, max_age: Duration) -> Self {
+        self.max_age = Some(max_age);
+        self
+    }
+
+    fn allows_origin(&self, origin: &str) -> bool {
+        self.any_origin || self.origins.iter().any(|allowed| allowed.matches(origin))
+    }
+
+    fn allow_origin_header(&self, origin: &HeaderValue) -> Option<HeaderValue> {
+        let origin_str = origin.to_str().ok()?;
+        if !self.allows_origin(origin_str) {
+            return None;
+        }
+
+        if self.any_origin {
+            Some(HeaderValue::from_static("*"))
+        } else {
+            Some(origin.clone())
+        }
+    }
+
+    /// Responses depend on the origin unless any origin gets a plain `*`.
+    fn vary_origin(&self, headers: &mut HeaderMap) {
+        if !self.any_origin {
+            headers.append(VARY, HeaderValue::from_static("origin"));
+        }
+    }
+
+    fn insert_common(&self, origin: HeaderValue, headers: &mut HeaderMap) {
+        headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, origin);
+
+        if self.credentials {
+            headers.insert(
+                ACCESS_CONTROL_ALLOW_CREDENTIALS,
+                HeaderValue::from_static("true"),
+            );
+        }
+    }
+
+    /// Answers a preflight for a path served with `registered` methods.
+    pub(crate) fn preflight(&self, req: &Request<Body>, registered: &[Method]) -> Response<Body> {
+        let forbidden = || {
+            let mut response = Response::builder()
+                .status(StatusCode::FORBIDDEN)
+                .body(Body::empty())
+                .unwrap();
+            self.vary_origin(response.headers_mut());
+            response
+        };
+
+        let origin = match req
+            .headers()
+            .get(ORIGIN)
+            .and_then(|origin| self.allow_origin_header(origin))
+        {
+            Some(origin) => origin,
+            None => return forbidden(),
+        };
+
+        let methods = self.methods.as_deref().unwrap_or(registered);
+        let requested = req
+            .headers()
+            .get(ACCESS_CONTROL_REQUEST_METHOD)
+            .and_then(|method| Method::from_bytes(method.as_bytes()).ok());
+
+        if !requested.is_some_and(|method| methods.contains(&method)) {
+            return forbidden();
+        }
+
+        let mut response = Response::builder()
+            .status(StatusCode::NO_CONTENT)
+            .body(Body::empty())
+            .unwrap();
+        let headers = response.headers_mut();
+        self.vary_origin(headers);
+        self.insert_common(origin, headers);
+
+        headers.insert(
+            ACCESS_CONTROL_ALLOW_METHODS,
+            join(methods.iter().map(Method::as_str)),
+        );
+
+        if self.any_header {
+            if let Some(requested) = req.headers().get(ACCESS_CONTROL_REQUEST_HEADERS) {
+                headers.insert(ACCESS_CONTROL_ALLOW_HEADERS, requested.clone());
+            }
+        } else if !self.headers.is_empty() {
+            headers.insert(
+                ACCESS_CONTROL_ALLOW_HEADERS,
+                join(self.headers.iter().map(HeaderName::as_str)),
+            );
+        }
+
+        if let Some(max_age) = self.max_age {
+            headers.insert(ACCESS_CONTROL_MAX_AGE, HeaderValue::from(max_age.as_secs()));
+        }
+
+        response
+    }
+
+    /// Adds CORS headers to the response of an actual request from `origin`.
+    pub(crate) fn apply(&self, origin: Option<&HeaderValue>, response: &mut Response<Body>) {
+        let headers = response.headers_mut();
+        self.vary_origin(headers);
+
+        let origin = match origin.and_then(|origin| self.allow_origin_header(origin)) {
+            Some(origin) => origin,
+            None => return,
+        };
+
+        self.insert_common(origin, headers);
+
+        if !self.expose_headers.is_empty() {
+            headers.insert(
+                ACCESS_CONTROL_EXPOSE_HEADERS,
+                join(self.expose_headers.iter().map(HeaderName::as_str)),
+            );
+        }
+    }
+}
+
+/// Whether the request is a CORS preflight rather than a plain OPTIONS request.
+pub(crate) fn is_preflight(req: &Request<Body>) -> bool {
+    req.method() == Method::OPTIONS
+        && req.headers().contains_key(ORIGIN)
+        && req.headers().contains_key(ACCESS_CONTROL_REQUEST_METHOD)
+}
+
+fn join<'a>(values: impl Iterator<Item = &'a str>) -> HeaderValue {
+    HeaderValue::from_str(&values.collect::<Vec<_>>().join(", ")).unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Condey, Route, TestClient};
+
+    fn route(method: Method, path: &str) -> Route {
+        Route::builder()
+            .method(method)
+            .path(path)
+            .with_handler_fn(|| async { "Hello!".to_string() })
+    }
+
+    #[tokio::test]
+    async fn preflight() {
+        let cors = Cors::new().allow_origin("https://*.example.com");
+        let routes = vec![
+            route(Method::GET, "/albums"),
+            route(Method::POST, "/albums"),
+        ];
+        let client = TestClient::new(Condey::init().cors(cors).mount("/api", routes));
+
+        client
+            .options("/api/albums")
+            .header("origin", "https://shop.example.com")
+            .header("access-control-request-method", "POST")
+            .send()
+            .await
+            .assert_status(StatusCode::NO_CONTENT)
+            .assert_header("access-control-allow-origin", "https://shop.example.com")
+            .assert_header("access-control-allow-methods", "GET, HEAD, OPTIONS, POST")
+            .assert_header("vary", "origin");
+
+        client
+            .options("/api/albums")
+            .header("origin", "https://example.net")
+            .header("access-control-request-method", "POST")
+            .send()
+            .await
+            .assert_status(StatusCode::FORBIDDEN)
+            .assert_no_header("access-control-allow-origin");
+    }
+
+    #[tokio::test]
+    async fn mount_policy() {
+        let cors = Cors::new()
+            .allow_origin("https://admin.example.org")
+            .allow_credentials(true)
+            .allow_headers(vec![HeaderName::from_static("x-token")])
+            .max_age(Duration::from_secs(600));
+        let routes = vec![route(Method::DELETE, "/users")];
+        let client = TestClient::new(Condey::init().mount_with_cors("/admin", routes, cors));
+
+        client
+            .options("/admin/users")
+            .header("origin", "https://admin.example.org")
+            .header("access-control-request-method", "DELETE")
+            .send()
+            .await
+            .assert_status(StatusCode::NO_CONTENT)
+            .assert_header("access-control-allow-credentials", "true")
+            .assert_header("access-control-allow-headers", "x-token")
+            .assert_header("access-control-max-age", "600");
+
+        client
+            .delete("/admin/users")
+            .header("origin", "https://shop.example.com")
+            .send()
+            .await
+            .assert_status(StatusCode::OK)
+            .assert_header("vary", "origin")
+            .assert_no_header("access-control-allow-origin");
+    }
+
+    #[tokio::test]
+    async fn actual_request() {
+        let cors = Cors::new().allow_origin("https://*.example.com");
+        let public = route(Method::GET, "/feed").cors(Cors::new().allow_origin("*"));
+        let client = TestClient::new(
+            Condey::init()
+                .cors(cors)
+                .mount("/api", vec![route(Method::GET, "/albums")])
+                .mount("/public", vec![public]),
+        );
+
+        client
+            .get("/api/albums")
+            .header("origin", "https://shop.example.com")
+            .send()
+            .await
+            .assert_status(StatusCode::OK)
+            .assert_header("access-control-allow-origin", "https://shop.example.com");
+
+        client
+            .get("/public/feed")
+            .header("origin", "https://anywhere.test")
+            .send()
+            .await
+            .assert_header("access-control-allow-origin", "*")
+            .assert_no_header("vary");
+    }
+
+    #[test]
+    #[should_panic(expected = "credentials cannot be allowed for any origin")]
+    fn any_origin_with_credentials() {
+        let _ = Cors::new().allow_credentials(true).allow_origin("*");
+    }
+}
@@ -14,4 +14,7 @@ pub trait Extract<'r, T: ExtractClass> {
     fn default_interceptor() -> Box<dyn Interceptor>;
 
     const TAKES_BODY: bool;
+
+    /// Extractor reads the body itself, so it must not be buffered up front.
+    const STREAMS_BODY: bool = false;
 }
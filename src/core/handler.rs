@@ -1,8 +1,9 @@
+use super::body_limit::body_error_response;
 use super::extract::{Extract, ExtractClass};
 use super::request::Request;
 use super::response::Responder;
 use crate::http::Response as HttpResponse;
-use crate::Body;
+use crate::{types::StreamedBody, Body};
 
 use futures::TryStreamExt;
 
@@ -13,6 +14,12 @@ pub trait Handler: Send + Sync + 'static {
         &self,
         request: Request,
     ) -> Pin<Box<dyn Future<Output = Result<HttpResponse<Body>, ()>> + Send>>;
+
+    /// Number of arguments consuming the request body. Routes refuse handlers
+    /// with more than one.
+    fn body_extractors(&self) -> usize {
+        0
+    }
 }
 
 #[derive(Clone, Copy)]
@@ -33,20 +40,24 @@ macro_rules! handler_for_async_fn {
             fn handle_request(&self, mut request: Request) -> Pin<Box<dyn Future<Output = Result<HttpResponse<Body>, ()>> + Send>> {
                 let fun = self.function;
                 let mut body = std::mem::replace(request.body_mut(), Body::empty());
-                let mut body_taken = false;
+                let streams_body = false $(|| $t::STREAMS_BODY)*;
 
                 Box::pin(async move {
-                    let body: Vec<u8> = body
-                        .map_ok(|chunk| chunk.into_iter().collect::<Vec<u8>>())
-                        .try_concat()
-                        .await.unwrap();
-
-                    $(
-                        if body_taken && $t::TAKES_BODY {
-                            return Err(())
+                    let body: Vec<u8> = if streams_body {
+                        request.extensions_mut().insert(StreamedBody::new(body));
+                        vec![]
+                    } else {
+                        match body
+                            .map_ok(|chunk| chunk.into_iter().collect::<Vec<u8>>())
+                            .try_concat()
+                            .await
+                        {
+                            Ok(body) => body,
+                            Err(err) => return Ok(body_error_response(err)),
                         }
+                    };
 
-
+                    $(
                         let $p = match $t::extract(&request, &body).await {
                             Ok(param) => param,
                             Err(error) => {
@@ -62,6 +73,10 @@ macro_rules! handler_for_async_fn {
                     Ok(result.respond_to(&request).await)
                 })
             }
+
+            fn body_extractors(&self) -> usize {
+                0 $(+ $t::TAKES_BODY as usize)*
+            }
         }
 
         impl<$($eclass: ExtractClass,)* $($t: for<'r> Extract<'r, $eclass> + Send + Sync + 'static,)* Fun, Fut> From<Fun> for HandlerFn<Fun, ($($eclass,)* Fut, $($t,)*)>
@@ -0,0 +1,506 @@
+use super::{config::ServerConfig, connection::Connected};
+
+use futures::{ready, task::AtomicWaker, Future};
+use hyper::{
+    body::{Bytes, HttpBody, SizeHint},
+    server::accept::Accept,
+    Body, HeaderMap,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, ReadBuf},
+    sync::{OwnedSemaphorePermit, Semaphore},
+    time::{sleep, Sleep},
+};
+use tracing::Span;
+
+use std::{
+    io,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::Duration,
+};
+
+#[derive(Debug, Clone, Copy)]
+struct Timeouts {
+    header_read: Option<Duration>,
+    idle: Option<Duration>,
+}
+
+/// Enforces [`ServerConfig`] connection limits and timeouts on accepted connections.
+pub(crate) struct LimitedIncoming<I> {
+    incoming: I,
+    permits: Option<Arc<Semaphore>>,
+    timeouts: Timeouts,
+}
+
+impl<I> LimitedIncoming<I> {
+    pub fn new(incoming: I, config: &ServerConfig, permits: Option<Arc<Semaphore>>) -> Self {
+        LimitedIncoming {
+            incoming,
+            permits,
+            timeouts: Timeouts {
+                header_read: config.header_read_timeout,
+                idle: config.keep_alive_timeout,
+            },
+        }
+    }
+}
+
+impl<I> Accept for LimitedIncoming<I>
+where
+    I: Accept + Unpin,
+    I::Conn: Connected,
+{
+    type Conn = LimitedConn<I::Conn>;
+    type Error = I::Error;
+
+    fn poll_accept(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
+        loop {
+            let io = match ready!(Pin::new(&mut self.incoming).poll_accept(cx)) {
+                Some(Ok(io)) => io,
+                Some(Err(err)) => return Poll::Ready(Some(Err(err))),
+                None => return Poll::Ready(None),
+            };
+
+            let span = tracing::info_span!("connection", remote = tracing::field::Empty);
+            if let Some(remote) = io.remote_addr() {
+                span.record("remote", tracing::field::display(remote));
+            }
+
+            let permit = match &self.permits {
+                Some(permits) => match permits.clone().try_acquire_owned() {
+                    Ok(permit) => Some(permit),
+                    Err(_) => {
+                        tracing::warn!(parent: &span, "connection limit reached, rejecting");
+                        continue;
+                    }
+                },
+                None => None,
+            };
+
+            return Poll::Ready(Some(Ok(LimitedConn::new(io, permit, span, self.timeouts))));
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Phase {
+    /// Waiting for the head of the next request.
+    ReadingHead,
+    /// At least one request is being handled.
+    Active,
+    /// Keep-alive connection without requests.
+    Idle,
+}
+
+pub(crate) struct LimitedConn<IO> {
+    io: IO,
+    _permit: Option<OwnedSemaphorePermit>,
+    span: Span,
+    requests: Arc<Requests>,
+    seen_requests: usize,
+    timeouts: Timeouts,
+    phase: Phase,
+    deadline: Option<Pin<Box<Sleep>>>,
+}
+
+impl<IO> LimitedConn<IO> {
+    fn new(io: IO, permit: Option<OwnedSemaphorePermit>, span: Span, timeouts: Timeouts) -> Self {
+        LimitedConn {
+            io,
+            _permit: permit,
+            span,
+            requests: Arc::default(),
+            seen_requests: 0,
+            timeouts,
+            phase: Phase::ReadingHead,
+            deadline: timeouts.header_read.map(|timeout| Box::pin(sleep(timeout))),
+        }
+    }
+
+    pub fn span(&self) -> &Span {
+        &self.span
+    }
+
+    /// Marks a request as in flight for as long as the returned guard lives.
+    pub fn request_tracker(&self) -> RequestTracker {
+        RequestTracker(self.requests.clone())
+    }
+
+    fn enter(&mut self, phase: Phase) {
+        self.phase = phase;
+        self.deadline = match phase {
+            Phase::ReadingHead => self.timeouts.header_read,
+            Phase::Idle => self.timeouts.idle,
+            Phase::Active => None,
+        }
+        .map(|timeout| Box::pin(sleep(timeout)));
+    }
+
+    fn update_phase(&mut self) {
+        // upgraded connections speak another protocol, their requests can't be told apart
+        if self.requests.upgraded.load(Ordering::SeqCst) {
+            if self.phase != Phase::Active {
+                self.enter(Phase::Active);
+            }
+            return;
+        }
+
+        let started = self.requests.started.load(Ordering::SeqCst);
+        let in_flight = self.requests.in_flight.load(Ordering::SeqCst) > 0;
+
+        if in_flight {
+            if self.phase != Phase::Active {
+                self.enter(Phase::Active);
+            }
+        } else if self.phase == Phase::Active || started != self.seen_requests {
+            // requests may start and finish between two polls of the connection
+            self.enter(Phase::Idle);
+        }
+
+        self.seen_requests = started;
+    }
+
+    fn on_read(&mut self) {
+        self.update_phase();
+
+        if self.phase == Phase::Idle {
+            self.enter(Phase::ReadingHead);
+        }
+    }
+
+    fn poll_deadline(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        self.requests.finished.register(cx.waker());
+        self.update_phase();
+
+        let deadline = match &mut self.deadline {
+            Some(deadline) => deadline,
+            None => return Poll::Pending,
+        };
+
+        ready!(deadline.as_mut().poll(cx));
+
+        let reason = match self.phase {
+            Phase::ReadingHead => "header read timeout",
+            _ => "keep-alive timeout",
+        };
+        tracing::warn!(parent: &self.span, "{} elapsed, closing connection", reason);
+
+        Poll::Ready(Err(io::Error::new(io::ErrorKind::TimedOut, reason)))
+    }
+}
+
+impl<IO: Connected> Connected for LimitedConn<IO> {
+    type Info = IO::Info;
+
+    fn connect_info(&self) -> Self::Info {
+        self.io.connect_info()
+    }
+
+    fn remote_addr(&self) -> Option<std::net::SocketAddr> {
+        self.io.remote_addr()
+    }
+
+    fn insert_info(info: &Self::Info, extensions: &mut crate::http::Extensions) {
+        IO::insert_info(info, extensions)
+    }
+}
+
+impl<IO: AsyncRead + Unpin> AsyncRead for LimitedConn<IO> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled = buf.filled().len();
+
+        match Pin::new(&mut self.io).poll_read(cx, buf) {
+            Poll::Ready(result) => {
+                if buf.filled().len() > filled {
+                    self.on_read();
+                }
+                Poll::Ready(result)
+            }
+            Poll::Pending => {
+                ready!(self.poll_deadline(cx))?;
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<IO: AsyncWrite + Unpin> AsyncWrite for LimitedConn<IO> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.io).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_shutdown(cx)
+    }
+}
+
+#[derive(Debug, Default)]
+struct Requests {
+    started: AtomicUsize,
+    in_flight: AtomicUsize,
+    upgraded: AtomicBool,
+    /// Wakes the connection to arm the keep-alive timer once a request finished.
+    finished: AtomicWaker,
+}
+
+#[derive(Debug, Clone)]
+pub(crate) struct RequestTracker(Arc<Requests>);
+
+impl RequestTracker {
+    pub fn start(&self) -> RequestGuard {
+        self.0.started.fetch_add(1, Ordering::SeqCst);
+        self.0.in_flight.fetch_add(1, Ordering::SeqCst);
+        RequestGuard(self.0.clone())
+    }
+
+    /// Turns the timeouts off for good once the connection switched protocols.
+    pub fn upgrade(&self) {
+        self.0.upgraded.store(true, Ordering::SeqCst);
+        self.0.finished.wake();
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct RequestGuard(Arc<Requests>);
+
+impl Drop for RequestGuard {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::SeqCst);
+        self.0.finished.wake();
+    }
+}
+
+/// Response body keeping its request in flight until it is sent completely,
+/// so streamed responses don't count as idle.
+pub(crate) struct TrackedBody {
+    body: Body,
+    guard: Option<RequestGuard>,
+}
+
+impl TrackedBody {
+    pub fn new(body: Body, guard: RequestGuard) -> Self {
+        TrackedBody {
+            body,
+            guard: Some(guard),
+        }
+    }
+}
+
+impl HttpBody for TrackedBody {
+    type Data = Bytes;
+    type Error = hyper::Error;
+
+    fn poll_data(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Data, Self::Error>>> {
+        let data = ready!(Pin::new(&mut self.body).poll_data(cx));
+        if !matches!(data, Some(Ok(_))) {
+            self.guard = None;
+        }
+
+        Poll::Ready(data)
+    }
+
+    fn poll_trailers(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Result<Option<HeaderMap>, Self::Error>> {
+        let trailers = ready!(Pin::new(&mut self.body).poll_trailers(cx));
+        self.guard = None;
+
+        Poll::Ready(trailers)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.body.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.body.size_hint()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        http::Method,
+        types::{Event, Sse, WebSocketUpgrade},
+        Condey, Listener, Route, ServerConfig,
+    };
+
+    use futures::{stream, SinkExt, StreamExt};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    };
+    use tokio_tungstenite::tungstenite::Message;
+
+    async fn spawn_server(config: ServerConfig) -> std::net::SocketAddr {
+        let listener = Listener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().tcp().unwrap();
+
+        let routes = vec![
+            Route::builder()
+                .method(Method::GET)
+                .path("/hello")
+                .with_handler_fn(|| async { "Hello!".to_string() }),
+            Route::builder()
+                .method(Method::GET)
+                .path("/events")
+                .with_handler_fn(|| async {
+                    let events = stream::iter(0..6).then(|tick: u32| async move {
+                        tokio::time::sleep(Duration::from_millis(50)).await;
+                        Event::data(tick.to_string())
+                    });
+                    Sse::new(events).no_keep_alive()
+                }),
+            Route::builder()
+                .method(Method::GET)
+                .path("/echo")
+                .with_handler_fn(|ws: WebSocketUpgrade| async move {
+                    ws.on_upgrade(|mut socket| async move {
+                        while let Some(Ok(message)) = socket.recv().await {
+                            if socket.send(message).await.is_err() {
+                                break;
+                            }
+                        }
+                    })
+                }),
+        ];
+
+        tokio::spawn(
+            Condey::init()
+                .mount("", routes)
+                .server_config(config)
+                .listen_on(listener),
+        );
+
+        addr
+    }
+
+    #[tokio::test]
+    async fn close_slow_headers() {
+        let config = ServerConfig::default().header_read_timeout(Duration::from_millis(50));
+        let addr = spawn_server(config).await;
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(b"GET /hello HTTP/1.1\r\n").await.unwrap();
+
+        let mut response = vec![];
+        let read = tokio::time::timeout(Duration::from_secs(1), stream.read_to_end(&mut response))
+            .await
+            .expect("connection not closed");
+
+        assert!(read.is_err() || response.is_empty());
+    }
+
+    #[tokio::test]
+    async fn close_idle_keep_alive() {
+        let config = ServerConfig::default().keep_alive_timeout(Duration::from_millis(50));
+        let addr = spawn_server(config).await;
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET /hello HTTP/1.1\r\nhost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+
+        let mut response = vec![];
+        tokio::time::timeout(Duration::from_secs(1), stream.read_to_end(&mut response))
+            .await
+            .expect("connection not closed")
+            .ok();
+
+        let response = String::from_utf8(response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with("Hello!"));
+    }
+
+    fn short_timeouts() -> ServerConfig {
+        ServerConfig::default()
+            .header_read_timeout(Duration::from_millis(120))
+            .keep_alive_timeout(Duration::from_millis(120))
+    }
+
+    #[tokio::test]
+    async fn keep_streaming_responses() {
+        let addr = spawn_server(short_timeouts()).await;
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET /events HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n")
+            .await
+            .unwrap();
+
+        let mut response = vec![];
+        stream.read_to_end(&mut response).await.unwrap();
+
+        let response = String::from_utf8(response).unwrap();
+        assert!(response.contains("data: 5\n\n"), "cut off: {:?}", response);
+    }
+
+    #[tokio::test]
+    async fn keep_upgraded_connections() {
+        let addr = spawn_server(short_timeouts()).await;
+
+        let tcp = TcpStream::connect(addr).await.unwrap();
+        let (mut client, _) = tokio_tungstenite::client_async(format!("ws://{}/echo", addr), tcp)
+            .await
+            .unwrap();
+
+        for tick in 0..6 {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            client.send(Message::Text(tick.to_string())).await.unwrap();
+            assert_eq!(
+                client.next().await.unwrap().unwrap(),
+                Message::Text(tick.to_string())
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn reject_over_limit() {
+        let config = ServerConfig::default().max_connections(1);
+        let addr = spawn_server(config).await;
+
+        let mut first = TcpStream::connect(addr).await.unwrap();
+        first
+            .write_all(b"GET /hello HTTP/1.1\r\nhost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut buf = [0; 16];
+        first.read_exact(&mut buf).await.unwrap();
+
+        let mut second = TcpStream::connect(addr).await.unwrap();
+        second
+            .write_all(b"GET /hello HTTP/1.1\r\nhost: localhost\r\n\r\n")
+            .await
+            .ok();
+
+        let mut response = vec![];
+        let read = second.read_to_end(&mut response).await;
+        assert!(read.is_err() || response.is_empty());
+    }
+}
@@ -0,0 +1,393 @@
+#[cfg(unix)]
+use super::unix::{UnixIncoming, UnixSocket};
+use super::{
+    condey::{serve_incoming, ServerError},
+    config::ServerConfig,
+    connection::Connected,
+    proxy::{ProxyIncoming, ProxyProtocol},
+    service::AppService,
+    shutdown::ConnectionCounter,
+    tls::{TlsConfig, TlsIncoming},
+};
+
+use futures::Future;
+use hyper::server::{accept::Accept, conn::AddrIncoming};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{lookup_host, TcpListener, ToSocketAddrs},
+    sync::Semaphore,
+};
+
+use tokio_rustls::TlsAcceptor;
+
+use std::{fmt, io, net::SocketAddr, sync::Arc};
+#[cfg(unix)]
+use std::{os::unix::io::RawFd, path::PathBuf};
+
+/// Address a [`Listener`] accepts connections on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ListenAddr {
+    Tcp(SocketAddr),
+    /// Filesystem path of the socket, `None` for abstract and unnamed sockets.
+    #[cfg(unix)]
+    Unix(Option<PathBuf>),
+}
+
+impl ListenAddr {
+    pub fn tcp(&self) -> Option<SocketAddr> {
+        match self {
+            ListenAddr::Tcp(addr) => Some(*addr),
+            #[cfg(unix)]
+            ListenAddr::Unix(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for ListenAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ListenAddr::Tcp(addr) => write!(f, "{}", addr),
+            #[cfg(unix)]
+            ListenAddr::Unix(Some(path)) => write!(f, "unix:{}", path.display()),
+            #[cfg(unix)]
+            ListenAddr::Unix(None) => write!(f, "unix:<unnamed>"),
+        }
+    }
+}
+
+enum ListenerKind {
+    Tcp(AddrIncoming),
+    #[cfg(unix)]
+    Unix(UnixIncoming),
+}
+
+/// A bound socket Condey can serve on.
+pub struct Listener {
+    kind: ListenerKind,
+    tls: Option<TlsConfig>,
+    proxy: Option<ProxyProtocol>,
+}
+
+impl Listener {
+    pub async fn bind(addr: impl ToSocketAddrs) -> Result<Self, ServerError> {
+        let addr = lookup_host(addr)
+            .await?
+            .next()
+            .ok_or(ServerError::NotResolvedError)?;
+
+        let incoming = AddrIncoming::bind(&addr)?;
+
+        Ok(Listener::tcp(incoming))
+    }
+
+    #[cfg(unix)]
+    pub fn unix(socket: &UnixSocket) -> Result<Self, ServerError> {
+        let incoming = socket.bind()?;
+
+        Ok(Listener::new(ListenerKind::Unix(incoming)))
+    }
+
+    /// Wraps an already bound standard library listener.
+    pub fn from_std(listener: std::net::TcpListener) -> Result<Self, ServerError> {
+        listener.set_nonblocking(true)?;
+
+        Ok(Listener::from_tcp(TcpListener::from_std(listener)?)?)
+    }
+
+    /// Wraps an already bound tokio listener.
+    pub fn from_tcp(listener: TcpListener) -> io::Result<Self> {
+        let incoming = AddrIncoming::from_listener(listener).map_err(io::Error::other)?;
+
+        Ok(Listener::tcp(incoming))
+    }
+
+    /// Picks up sockets passed through systemd's `LISTEN_FDS`/`LISTEN_PID` protocol.
+    /// Returns no listeners when the process was not socket activated.
+    #[cfg(unix)]
+    pub fn from_systemd() -> Result<Vec<Self>, ServerError> {
+        const SD_LISTEN_FDS_START: RawFd = 3;
+
+        let pid = match std::env::var("LISTEN_PID") {
+            Ok(pid) => pid,
+            Err(_) => return Ok(vec![]),
+        };
+
+        if pid.parse::<u32>().ok() != Some(std::process::id()) {
+            tracing::debug!("LISTEN_PID={} is meant for another process", pid);
+            return Ok(vec![]);
+        }
+
+        let fds = std::env::var("LISTEN_FDS")
+            .map_err(|err| ServerError::SocketActivationError(format!("LISTEN_FDS: {}", err)))?
+            .parse::<RawFd>()
+            .map_err(|err| ServerError::SocketActivationError(format!("LISTEN_FDS: {}", err)))?;
+
+        std::env::remove_var("LISTEN_PID");
+        std::env::remove_var("LISTEN_FDS");
+        std::env::remove_var("LISTEN_FDNAMES");
+
+        (SD_LISTEN_FDS_START..SD_LISTEN_FDS_START + fds)
+            .map(Listener::from_raw_fd)
+            .collect()
+    }
+
+    #[cfg(unix)]
+    fn from_raw_fd(fd: RawFd) -> Result<Self, ServerError> {
+        use socket2::Socket;
+        use std::os::unix::io::FromRawFd;
+
+        // SAFETY: systemd hands the descriptors over to this process exclusively
+        let socket = unsafe { Socket::from_raw_fd(fd) };
+        socket.set_cloexec(true)?;
+        socket.set_nonblocking(true)?;
+
+        let local_addr = socket.local_addr()?;
+
+        if local_addr.is_ipv4() || local_addr.is_ipv6() {
+            tracing::info!("inherited TCP socket (fd {})", fd);
+            Ok(Listener::from_tcp(TcpListener::from_std(socket.into())?)?)
+        } else if local_addr.is_unix() {
+            tracing::info!("inherited Unix socket (fd {})", fd);
+            let listener = tokio::net::UnixListener::from_std(socket.into())?;
+            Ok(Listener::from(listener))
+        } else {
+            Err(ServerError::SocketActivationError(format!(
+                "fd {} is neither a TCP nor a Unix socket",
+                fd
+            )))
+        }
+    }
+
+    /// Terminates TLS on accepted connections.
+    pub fn tls(mut self, tls: TlsConfig) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Reads PROXY protocol headers sent by load balancers in front of the
+    /// listener, before TLS is terminated.
+    pub fn proxy_protocol(mut self, proxy: ProxyProtocol) -> Self {
+        self.proxy = Some(proxy);
+        self
+    }
+
+    pub fn local_addr(&self) -> io::Result<ListenAddr> {
+        match &self.kind {
+            ListenerKind::Tcp(incoming) => Ok(ListenAddr::Tcp(incoming.local_addr())),
+            #[cfg(unix)]
+            ListenerKind::Unix(incoming) => incoming.local_addr().map(ListenAddr::Unix),
+        }
+    }
+
+    fn new(kind: ListenerKind) -> Self {
+        Listener {
+            kind,
+            tls: None,
+            proxy: None,
+        }
+    }
+
+    fn tcp(mut incoming: AddrIncoming) -> Self {
+        incoming.set_nodelay(true);
+
+        Listener::new(ListenerKind::Tcp(incoming))
+    }
+
+    pub(crate) async fn serve<F>(
+        self,
+        app: AppService,
+        config: &ServerConfig,
+        connections: ConnectionCounter,
+        permits: Option<Arc<Semaphore>>,
+        signal: F,
+    ) -> Result<(), ServerError>
+    where
+        F: Future<Output = ()>,
+    {
+        let addr = self.local_addr()?;
+        let acceptor = self.tls.as_ref().map(TlsConfig::acceptor).transpose()?;
+
+        tracing::info!(
+            "listening on {}{}{}",
+            addr,
+            if acceptor.is_some() { " (TLS)" } else { "" },
+            if self.proxy.is_some() {
+                " (PROXY protocol)"
+            } else {
+                ""
+            }
+        );
+
+        match (self.kind, self.proxy) {
+            (ListenerKind::Tcp(incoming), None) => {
+                serve_tls(
+                    incoming,
+                    acceptor,
+                    app,
+                    config,
+                    connections,
+                    permits,
+                    signal,
+                )
+                .await
+            }
+            (ListenerKind::Tcp(incoming), Some(proxy)) => {
+                let incoming = ProxyIncoming::new(incoming, proxy);
+                serve_tls(
+                    incoming,
+                    acceptor,
+                    app,
+                    config,
+                    connections,
+                    permits,
+                    signal,
+                )
+                .await
+            }
+            #[cfg(unix)]
+            (ListenerKind::Unix(incoming), None) => {
+                serve_tls(
+                    incoming,
+                    acceptor,
+                    app,
+                    config,
+                    connections,
+                    permits,
+                    signal,
+                )
+                .await
+            }
+            #[cfg(unix)]
+            (ListenerKind::Unix(incoming), Some(proxy)) => {
+                let incoming = ProxyIncoming::new(incoming, proxy);
+                serve_tls(
+                    incoming,
+                    acceptor,
+                    app,
+                    config,
+                    connections,
+                    permits,
+                    signal,
+                )
+                .await
+            }
+        }
+    }
+}
+
+async fn serve_tls<I, F>(
+    incoming: I,
+    acceptor: Option<TlsAcceptor>,
+    app: AppService,
+    config: &ServerConfig,
+    connections: ConnectionCounter,
+    permits: Option<Arc<Semaphore>>,
+    signal: F,
+) -> Result<(), ServerError>
+where
+    I: Accept + Unpin,
+    I::Conn: AsyncRead + AsyncWrite + Connected + Unpin + Send + 'static,
+    I::Error: Into<Box<dyn std::error::Error + Send + Sync>>,
+    F: Future<Output = ()>,
+{
+    match acceptor {
+        None => serve_incoming(incoming, app, config, connections, permits, signal).await,
+        Some(acceptor) => {
+            let incoming = TlsIncoming::new(incoming, acceptor);
+            serve_incoming(incoming, app, config, connections, permits, signal).await
+        }
+    }
+}
+
+#[cfg(unix)]
+impl From<tokio::net::UnixListener> for Listener {
+    fn from(listener: tokio::net::UnixListener) -> Self {
+        Listener::new(ListenerKind::Unix(UnixIncoming::from(listener)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{http::Method, Condey, Route};
+
+    use hyper::{Client, StatusCode};
+
+    #[tokio::test]
+    async fn serve_prebound_listener() {
+        let std_listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let listener = Listener::from_std(std_listener).unwrap();
+        let addr = listener.local_addr().unwrap().tcp().unwrap();
+        assert_ne!(addr.port(), 0);
+
+        let routes = vec![Route::builder()
+            .method(Method::GET)
+            .path("/hello")
+            .with_handler_fn(|| async { "Hello!".to_string() })];
+
+        tokio::spawn(Condey::init().mount("", routes).listen_on(listener));
+
+        let response = Client::new()
+            .get(format!("http://{}/hello", addr).parse().unwrap())
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(&body[..], b"Hello!");
+    }
+
+    #[tokio::test]
+    async fn serve_multiple_listeners() {
+        let public = Listener::bind("127.0.0.1:0").await.unwrap();
+        let admin = Listener::bind("127.0.0.1:0").await.unwrap();
+        let addrs = vec![
+            public.local_addr().unwrap().tcp().unwrap(),
+            admin.local_addr().unwrap().tcp().unwrap(),
+        ];
+
+        let routes = vec![Route::builder()
+            .method(Method::GET)
+            .path("/hello")
+            .with_handler_fn(|| async { "Hello!".to_string() })];
+
+        let (tx, rx) = futures::channel::oneshot::channel::<()>();
+        let server = tokio::spawn(
+            Condey::init()
+                .mount("", routes)
+                .graceful_shutdown(async move {
+                    let _ = rx.await;
+                })
+                .listen_on_all(vec![public, admin]),
+        );
+
+        for addr in addrs {
+            let response = Client::new()
+                .get(format!("http://{}/hello", addr).parse().unwrap())
+                .await
+                .unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        tx.send(()).unwrap();
+        server.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn refuse_no_listeners() {
+        let result = Condey::init().listen_on_all(vec![]).await;
+        assert!(matches!(result, Err(ServerError::NoListenerError)));
+    }
+
+    #[test]
+    fn not_socket_activated() {
+        std::env::remove_var("LISTEN_PID");
+        assert!(Listener::from_systemd().unwrap().is_empty());
+    }
+
+    #[test]
+    fn listen_addr_display() {
+        let addr = ListenAddr::Tcp("127.0.0.1:3000".parse().unwrap());
+        assert_eq!(addr.to_string(), "127.0.0.1:3000");
+    }
+}
@@ -1,11 +1,28 @@
+mod body_limit;
+pub(super) mod compression;
 pub(super) mod condey;
-mod extract;
+pub(super) mod config;
+pub(super) mod connection;
+pub(super) mod cors;
+pub(super) mod extract;
 pub(super) mod from_body;
 pub(super) mod from_request;
 pub(super) mod handler;
 pub(super) mod interceptor;
+mod limits;
+pub(super) mod listener;
 pub(super) mod param;
+pub(super) mod proxy;
+mod range;
 pub(super) mod request;
 pub(super) mod response;
 pub(super) mod route;
+pub(super) mod route_table;
+pub(super) mod service;
+pub(super) mod shutdown;
 pub(super) mod state;
+pub(super) mod static_files;
+pub(super) mod test_client;
+pub(super) mod tls;
+#[cfg(unix)]
+pub(super) mod unix;
@@ -0,0 +1,490 @@
+use super::{connection::Connected, from_request::FromRequest};
+use crate::{http::Extensions, Interceptor, Request};
+
+use anyhow::anyhow;
+use futures::ready;
+use hyper::{server::accept::Accept, StatusCode};
+use ipnet::IpNet;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+use std::{
+    convert::TryInto,
+    fmt, io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    pin::Pin,
+    sync::{Arc, OnceLock},
+    task::{Context, Poll},
+};
+
+const V1_PREFIX: &[u8] = b"PROXY ";
+const V1_MAX_LEN: usize = 107;
+const V2_SIGNATURE: &[u8] = b"\r\n\r\n\0\r\nQUIT\n";
+const V2_HEADER_LEN: usize = 16;
+
+/// Parses HAProxy PROXY protocol (v1 and v2) headers on accepted connections.
+///
+/// Connections from trusted peers must start with a PROXY header and are closed
+/// otherwise. Connections from other peers are served as they are. Without any
+/// trusted network every peer is trusted.
+#[derive(Debug, Clone, Default)]
+pub struct ProxyProtocol {
+    trusted: Vec<IpNet>,
+}
+
+impl ProxyProtocol {
+    pub fn new() -> Self {
+        ProxyProtocol::default()
+    }
+
+    /// Accepts PROXY headers from peers within `network`.
+    pub fn trust(mut self, network: IpNet) -> Self {
+        self.trusted.push(network);
+        self
+    }
+
+    fn trusts(&self, peer: Option<SocketAddr>) -> bool {
+        match peer {
+            Some(peer) => {
+                self.trusted.is_empty()
+                    || self
+                        .trusted
+                        .iter()
+                        .any(|network| network.contains(&peer.ip()))
+            }
+            // local sockets
+            None => true,
+        }
+    }
+}
+
+/// Address of the client that sent the request, taken from the PROXY header
+/// when the listener parses it, or from the TCP peer otherwise.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientAddr {
+    addr: SocketAddr,
+    proxied: bool,
+}
+
+impl ClientAddr {
+    pub(crate) fn direct(addr: SocketAddr) -> Self {
+        ClientAddr {
+            addr,
+            proxied: false,
+        }
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn ip(&self) -> IpAddr {
+        self.addr.ip()
+    }
+
+    /// Whether the address was reported by a proxy through the PROXY protocol.
+    pub fn is_proxied(&self) -> bool {
+        self.proxied
+    }
+}
+
+impl fmt::Display for ClientAddr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.addr)
+    }
+}
+
+#[async_trait::async_trait]
+impl<'r> FromRequest<'r> for ClientAddr {
+    type Error = anyhow::Error;
+
+    async fn from_request(request: &'r Request) -> Result<Self, Self::Error> {
+        request
+            .extensions()
+            .get::<ClientAddr>()
+            .copied()
+            .ok_or_else(|| anyhow!("client address is unknown"))
+    }
+
+    fn default_interceptor() -> Box<dyn Interceptor> {
+        Box::new(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+}
+
+pub(crate) struct ProxyIncoming<I> {
+    incoming: I,
+    proxy: ProxyProtocol,
+}
+
+impl<I> ProxyIncoming<I> {
+    pub fn new(incoming: I, proxy: ProxyProtocol) -> Self {
+        ProxyIncoming { incoming, proxy }
+    }
+}
+
+impl<I> Accept for ProxyIncoming<I>
+where
+    I: Accept + Unpin,
+    I::Conn: Connected,
+{
+    type Conn = ProxyStream<I::Conn>;
+    type Error = I::Error;
+
+    fn poll_accept(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
+        let this = &mut *self;
+
+        match ready!(Pin::new(&mut this.incoming).poll_accept(cx)) {
+            Some(Ok(io)) => {
+                let peer = io.remote_addr();
+                let trusted = this.proxy.trusts(peer);
+                if !trusted {
+                    tracing::debug!("peer {:?} is not a trusted proxy", peer);
+                }
+
+                Poll::Ready(Some(Ok(ProxyStream::new(io, trusted))))
+            }
+            Some(Err(err)) => Poll::Ready(Some(Err(err))),
+            None => Poll::Ready(None),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct ProxyInfo<T> {
+    inner: T,
+    client: Arc<OnceLock<SocketAddr>>,
+}
+
+enum ProxyState {
+    /// Reading the PROXY header, the buffer may hold more than the header.
+    Header(Vec<u8>),
+    /// Replaying bytes read past the header.
+    Buffered(Vec<u8>, usize),
+    Passthrough,
+}
+
+/// Connection reading the PROXY header lazily, before the first read of the
+/// wrapped stream, so a slow proxy never blocks the accept loop.
+pub(crate) struct ProxyStream<IO: Connected> {
+    io: IO,
+    state: ProxyState,
+    info: ProxyInfo<IO::Info>,
+}
+
+impl<IO: Connected> ProxyStream<IO> {
+    fn new(io: IO, trusted: bool) -> Self {
+        let info = ProxyInfo {
+            inner: io.connect_info(),
+            client: Arc::default(),
+        };
+        let state = if trusted {
+            ProxyState::Header(Vec::with_capacity(V1_MAX_LEN))
+        } else {
+            ProxyState::Passthrough
+        };
+
+        ProxyStream { io, state, info }
+    }
+}
+
+impl<IO: Connected> Connected for ProxyStream<IO> {
+    type Info = ProxyInfo<IO::Info>;
+
+    fn connect_info(&self) -> Self::Info {
+        self.info.clone()
+    }
+
+    fn remote_addr(&self) -> Option<SocketAddr> {
+        self.io.remote_addr()
+    }
+
+    fn insert_info(info: &Self::Info, extensions: &mut Extensions) {
+        IO::insert_info(&info.inner, extensions);
+
+        if let Some(addr) = info.client.get() {
+            extensions.insert(ClientAddr {
+                addr: *addr,
+                proxied: true,
+            });
+        }
+    }
+}
+
+impl<IO: AsyncRead + Connected + Unpin> AsyncRead for ProxyStream<IO> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+
+        loop {
+            match &mut this.state {
+                ProxyState::Passthrough => return Pin::new(&mut this.io).poll_read(cx, buf),
+                ProxyState::Buffered(buffered, pos) => {
+                    let len = buf.remaining().min(buffered.len() - *pos);
+                    buf.put_slice(&buffered[*pos..*pos + len]);
+                    *pos += len;
+
+                    if *pos == buffered.len() {
+                        this.state = ProxyState::Passthrough;
+                    }
+
+                    return Poll::Ready(Ok(()));
+                }
+                ProxyState::Header(header) => {
+                    let mut chunk = [0; 512];
+                    let mut chunk = ReadBuf::new(&mut chunk);
+                    ready!(Pin::new(&mut this.io).poll_read(cx, &mut chunk))?;
+
+                    if chunk.filled().is_empty() {
+                        return Poll::Ready(Err(io::Error::new(
+                            io::ErrorKind::UnexpectedEof,
+                            "connection closed before PROXY header",
+                        )));
+                    }
+                    header.extend_from_slice(chunk.filled());
+
+                    let (len, client) = match parse_header(header) {
+                        Ok(Some(parsed)) => parsed,
+                        Ok(None) => continue,
+                        Err(err) => {
+                            tracing::debug!("invalid PROXY header: {}", err);
+                            return Poll::Ready(Err(err));
+                        }
+                    };
+
+                    if let Some(client) = client {
+                        tracing::debug!("PROXY header reports client {}", client);
+                        let _ = this.info.client.set(client);
+                    }
+
+                    let rest = header.split_off(len);
+                    this.state = if rest.is_empty() {
+                        ProxyState::Passthrough
+                    } else {
+                        ProxyState::Buffered(rest, 0)
+                    };
+                }
+            }
+        }
+    }
+}
+
+impl<IO: AsyncWrite + Connected + Unpin> AsyncWrite for ProxyStream<IO> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.io).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.io).poll_shutdown(cx)
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg.to_string())
+}
+
+/// Returns the header length and the reported client address once the whole
+/// header was read. `LOCAL` and `UNKNOWN` headers report no address.
+fn parse_header(buf: &[u8]) -> io::Result<Option<(usize, Option<SocketAddr>)>> {
+    if buf.starts_with(V1_PREFIX) {
+        parse_v1(buf)
+    } else if buf.starts_with(V2_SIGNATURE) {
+        parse_v2(buf)
+    } else if V1_PREFIX.starts_with(buf) || V2_SIGNATURE.starts_with(buf) {
+        Ok(None)
+    } else {
+        Err(invalid("missing PROXY header"))
+    }
+}
+
+fn parse_v1(buf: &[u8]) -> io::Result<Option<(usize, Option<SocketAddr>)>> {
+    let end = match buf.windows(2).position(|window| window == b"\r\n") {
+        Some(end) if end + 2 <= V1_MAX_LEN => end,
+        Some(_) => return Err(invalid("PROXY v1 header too long")),
+        None if buf.len() >= V1_MAX_LEN => return Err(invalid("PROXY v1 header too long")),
+        None => return Ok(None),
+    };
+
+    let line =
+        std::str::from_utf8(&buf[..end]).map_err(|_| invalid("PROXY v1 header not ASCII"))?;
+    let parts = line.split(' ').collect::<Vec<_>>();
+
+    let client = match parts.as_slice() {
+        ["PROXY", "UNKNOWN", ..] => None,
+        ["PROXY", family, src, _dst, src_port, _dst_port] => {
+            let ip = src
+                .parse::<IpAddr>()
+                .map_err(|_| invalid("invalid PROXY v1 source address"))?;
+            let port = src_port
+                .parse::<u16>()
+                .map_err(|_| invalid("invalid PROXY v1 source port"))?;
+
+            match (*family, ip) {
+                ("TCP4", IpAddr::V4(_)) | ("TCP6", IpAddr::V6(_)) => {}
+                _ => return Err(invalid("invalid PROXY v1 address family")),
+            }
+
+            Some(SocketAddr::new(ip, port))
+        }
+        _ => return Err(invalid("malformed PROXY v1 header")),
+    };
+
+    Ok(Some((end + 2, client)))
+}
+
+fn parse_v2(buf: &[u8]) -> io::Result<Option<(usize, Option<SocketAddr>)>> {
+    if buf.len() < V2_HEADER_LEN {
+        return Ok(None);
+    }
+
+    let version = buf[12] >> 4;
+    let command = buf[12] & 0x0f;
+    let family = buf[13] >> 4;
+    let len = V2_HEADER_LEN + u16::from_be_bytes([buf[14], buf[15]]) as usize;
+
+    if version != 2 {
+        return Err(invalid("unsupported PROXY protocol version"));
+    }
+
+    if buf.len() < len {
+        return Ok(None);
+    }
+
+    let addrs = &buf[V2_HEADER_LEN..len];
+    let client = match (command, family) {
+        // LOCAL, e.g. health checks of the proxy itself
+        (0x0, _) => None,
+        (0x1, 0x1) if addrs.len() >= 12 => {
+            let ip: [u8; 4] = addrs[0..4].try_into().unwrap();
+            let port = u16::from_be_bytes([addrs[8], addrs[9]]);
+            Some(SocketAddr::new(Ipv4Addr::from(ip).into(), port))
+        }
+        (0x1, 0x2) if addrs.len() >= 36 => {
+            let ip: [u8; 16] = addrs[0..16].try_into().unwrap();
+            let port = u16::from_be_bytes([addrs[32], addrs[33]]);
+            Some(SocketAddr::new(Ipv6Addr::from(ip).into(), port))
+        }
+        (0x1, 0x1) | (0x1, 0x2) => return Err(invalid("truncated PROXY v2 addresses")),
+        // UNSPEC and Unix sockets
+        (0x1, _) => None,
+        _ => return Err(invalid("unsupported PROXY v2 command")),
+    };
+
+    Ok(Some((len, client)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{http::Method, Condey, Listener, Route};
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    };
+
+    async fn spawn_server(proxy: ProxyProtocol) -> SocketAddr {
+        let listener = Listener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .proxy_protocol(proxy);
+        let addr = listener.local_addr().unwrap().tcp().unwrap();
+
+        let routes = vec![Route::builder()
+            .method(Method::GET)
+            .path("/client")
+            .with_handler_fn(|client: ClientAddr| async move {
+                format!("{} {}", client, client.is_proxied())
+            })];
+
+        tokio::spawn(Condey::init().mount("", routes).listen_on(listener));
+
+        addr
+    }
+
+    async fn request(addr: SocketAddr, preamble: &[u8]) -> String {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(preamble).await.unwrap();
+        stream
+            .write_all(b"GET /client HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n")
+            .await
+            .unwrap();
+
+        let mut response = vec![];
+        stream.read_to_end(&mut response).await.ok();
+
+        String::from_utf8(response).unwrap()
+    }
+
+    #[test]
+    fn parse_v1_header() {
+        let header = b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 443\r\nGET /";
+        let (len, client) = parse_header(header).unwrap().unwrap();
+
+        assert_eq!(&header[len..], b"GET /");
+        assert_eq!(client, Some("192.0.2.1:56324".parse().unwrap()));
+
+        assert!(parse_header(b"PROXY TCP6 2001:db8::1").unwrap().is_none());
+        assert_eq!(
+            parse_header(b"PROXY UNKNOWN\r\n").unwrap(),
+            Some((15, None))
+        );
+        assert!(parse_header(b"PROXY TCP4 2001:db8::1 ::1 1 2\r\n").is_err());
+        assert!(parse_header(b"GET / HTTP/1.1\r\n").is_err());
+    }
+
+    #[test]
+    fn parse_v2_header() {
+        let mut header = V2_SIGNATURE.to_vec();
+        header.extend_from_slice(&[0x21, 0x11, 0, 12]);
+        header.extend_from_slice(&[192, 0, 2, 1, 198, 51, 100, 1]);
+        header.extend_from_slice(&56324u16.to_be_bytes());
+        header.extend_from_slice(&443u16.to_be_bytes());
+
+        assert!(parse_header(&header[..20]).unwrap().is_none());
+        assert_eq!(
+            parse_header(&header).unwrap(),
+            Some((28, Some("192.0.2.1:56324".parse().unwrap())))
+        );
+
+        let mut local = V2_SIGNATURE.to_vec();
+        local.extend_from_slice(&[0x20, 0x00, 0, 0]);
+        assert_eq!(parse_header(&local).unwrap(), Some((16, None)));
+    }
+
+    #[tokio::test]
+    async fn proxied_client_addr() {
+        let addr = spawn_server(ProxyProtocol::new()).await;
+
+        let response = request(addr, b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 80\r\n").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with("192.0.2.1:56324 true"));
+
+        let response = request(addr, b"").await;
+        assert!(response.is_empty());
+    }
+
+    #[tokio::test]
+    async fn untrusted_peer() {
+        let addr = spawn_server(ProxyProtocol::new().trust("10.0.0.0/8".parse().unwrap())).await;
+
+        let response = request(addr, b"").await;
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with(" false"));
+
+        let response = request(addr, b"PROXY TCP4 192.0.2.1 198.51.100.1 56324 80\r\n").await;
+        assert!(!response.starts_with("HTTP/1.1 200 OK"));
+    }
+}
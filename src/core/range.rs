@@ -0,0 +1,524 @@
+use crate::{
+    http::{
+        header::{
+            ACCEPT_RANGES, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, IF_RANGE, LAST_MODIFIED,
+            RANGE,
+        },
+        HeaderMap, HeaderValue, Method, Response, StatusCode,
+    },
+    types::ETag,
+    Body,
+};
+
+use futures::{ready, stream, Stream, StreamExt, TryStreamExt};
+use hyper::body::{Bytes, HttpBody};
+use tokio::{
+    fs::File,
+    io::{AsyncReadExt, AsyncSeekExt},
+};
+use tokio_util::io::ReaderStream;
+
+use std::{
+    io::SeekFrom,
+    ops::Range,
+    path::PathBuf,
+    pin::Pin,
+    sync::atomic::{AtomicU64, Ordering},
+    task::{Context, Poll},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// More ranges than this in one request are ignored and the whole body is sent.
+const MAX_RANGES: usize = 32;
+
+/// File a response body was read from, so its ranges are read with a seek
+/// instead of skipping through the body.
+pub(crate) struct FileSource(pub PathBuf);
+
+/// `Range` and `If-Range` headers of a `GET` request.
+pub(crate) struct RangeRequest {
+    range: Option<HeaderValue>,
+    if_range: Option<HeaderValue>,
+}
+
+impl RangeRequest {
+    pub fn from_headers(method: &Method, headers: &HeaderMap) -> Self {
+        let range = match *method {
+            Method::GET => headers.get(RANGE).cloned(),
+            _ => None,
+        };
+
+        RangeRequest {
+            range,
+            if_range: headers.get(IF_RANGE).cloned(),
+        }
+    }
+
+    /// Advertises range support on complete responses of known length and
+    /// answers range requests with `206 Partial Content` or `416 Range Not
+    /// Satisfiable`.
+    pub async fn apply(&self, response: &mut Response<Body>) {
+        if response.status() != StatusCode::OK {
+            return;
+        }
+
+        let len = match content_length(response) {
+            Some(len) => len,
+            None => return,
+        };
+        response
+            .headers_mut()
+            .insert(ACCEPT_RANGES, HeaderValue::from_static("bytes"));
+
+        let range = match self.range.as_ref().and_then(|range| range.to_str().ok()) {
+            Some(range) if self.if_range_matches(response.headers()) => range,
+            _ => return,
+        };
+
+        match parse_ranges(range, len) {
+            Some(Ok(ranges)) => partial(response, ranges, len).await,
+            Some(Err(())) => {
+                *response = Response::builder()
+                    .status(StatusCode::RANGE_NOT_SATISFIABLE)
+                    .header(CONTENT_RANGE, format!("bytes */{}", len))
+                    .header(ACCEPT_RANGES, "bytes")
+                    .body(Body::empty())
+                    .unwrap()
+            }
+            None => {}
+        }
+    }
+
+    /// Ranges only apply to the representation the client already has parts of.
+    fn if_range_matches(&self, headers: &HeaderMap) -> bool {
+        let if_range = match self.if_range.as_ref().and_then(|value| value.to_str().ok()) {
+            Some(if_range) => if_range,
+            None => return true,
+        };
+
+        if let Ok(date) = httpdate::parse_http_date(if_range) {
+            return headers
+                .get(LAST_MODIFIED)
+                .and_then(|modified| modified.to_str().ok())
+                .and_then(|modified| httpdate::parse_http_date(modified).ok())
+                .is_some_and(|modified| modified == date);
+        }
+
+        match (if_range.parse::<ETag>(), ETag::from_headers(headers)) {
+            (Ok(expected), Some(current)) => expected.strong_eq(&current),
+            _ => false,
+        }
+    }
+}
+
+fn content_length(response: &Response<Body>) -> Option<u64> {
+    response
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|len| len.to_str().ok())
+        .and_then(|len| len.parse().ok())
+        .or_else(|| HttpBody::size_hint(response.body()).exact())
+}
+
+/// Parses a `bytes` range header for a body of `len` bytes into sorted ranges,
+/// merging those that overlap or touch. `None` means the header is ignored,
+/// `Err` that none of the ranges is satisfiable.
+///
+/// Ranges asking for more than the whole body in total are ignored as well,
+/// so overlapping ranges can't multiply the response (RFC 9110, 14.2).
+fn parse_ranges(header: &str, len: u64) -> Option<Result<Vec<Range<u64>>, ()>> {
+    let specs = header.trim().strip_prefix("bytes=")?;
+
+    let mut ranges = vec![];
+    let mut satisfiable = vec![];
+    for spec in specs.split(',').map(str::trim) {
+        let (first, last) = spec.split_once('-')?;
+        let range = match (first.trim(), last.trim()) {
+            ("", suffix) => {
+                let suffix = suffix.parse::<u64>().ok()?;
+                len.saturating_sub(suffix)..len
+            }
+            (first, "") => first.parse::<u64>().ok()?..len,
+            (first, last) => {
+                let first = first.parse::<u64>().ok()?;
+                let last = last.parse::<u64>().ok()?;
+                if last < first {
+                    return None;
+                }
+                first..last.saturating_add(1).min(len)
+            }
+        };
+
+        ranges.push(range.clone());
+        if range.start < range.end {
+            satisfiable.push(range);
+        }
+    }
+
+    if ranges.is_empty() || ranges.len() > MAX_RANGES {
+        return None;
+    }
+    if satisfiable.is_empty() {
+        return Some(Err(()));
+    }
+
+    let requested = satisfiable
+        .iter()
+        .map(|range| range.end - range.start)
+        .sum::<u64>();
+    if requested > len {
+        return None;
+    }
+
+    satisfiable.sort_by_key(|range| range.start);
+    let mut merged: Vec<Range<u64>> = Vec::with_capacity(satisfiable.len());
+    for range in satisfiable {
+        match merged.last_mut() {
+            Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+            _ => merged.push(range),
+        }
+    }
+
+    Some(Ok(merged))
+}
+
+async fn partial(response: &mut Response<Body>, ranges: Vec<Range<u64>>, len: u64) {
+    let body = std::mem::replace(response.body_mut(), Body::empty());
+    let file = response.extensions_mut().remove::<FileSource>();
+
+    *response.status_mut() = StatusCode::PARTIAL_CONTENT;
+
+    if let [range] = &ranges[..] {
+        let headers = response.headers_mut();
+        headers.insert(CONTENT_RANGE, content_range(range, len));
+        headers.insert(CONTENT_LENGTH, HeaderValue::from(range.end - range.start));
+
+        *response.body_mut() = match file {
+            Some(FileSource(path)) => file_part(path, range.clone()),
+            None => Body::wrap_stream(Slice::new(body, range.clone())),
+        };
+
+        return;
+    }
+
+    let parts: Vec<Body> = match file {
+        Some(FileSource(path)) => ranges
+            .iter()
+            .map(|range| file_part(path.clone(), range.clone()))
+            .collect(),
+        None => match hyper::body::to_bytes(body).await {
+            Ok(body) => ranges
+                .iter()
+                .map(|range| Body::from(body.slice(range.start as usize..range.end as usize)))
+                .collect(),
+            Err(err) => {
+                tracing::error!("failed to buffer response body: {}", err);
+                *response = Response::builder()
+                    .status(StatusCode::INTERNAL_SERVER_ERROR)
+                    .body(Body::empty())
+                    .unwrap();
+                return;
+            }
+        },
+    };
+
+    let boundary = boundary();
+    let headers = response.headers_mut();
+    let content_type = headers.remove(CONTENT_TYPE);
+    let part_headers = ranges
+        .iter()
+        .map(|range| {
+            let mut head = format!("\r\n--{}\r\n", boundary);
+            if let Some(content_type) = content_type.as_ref().and_then(|ty| ty.to_str().ok()) {
+                head.push_str(&format!("content-type: {}\r\n", content_type));
+            }
+            head.push_str(&format!(
+                "content-range: bytes {}-{}/{}\r\n\r\n",
+                range.start,
+                range.end - 1,
+                len
+            ));
+            Bytes::from(head)
+        })
+        .collect::<Vec<_>>();
+    let tail = Bytes::from(format!("\r\n--{}--\r\n", boundary));
+
+    let total = part_headers
+        .iter()
+        .map(|head| head.len() as u64)
+        .sum::<u64>()
+        + ranges
+            .iter()
+            .map(|range| range.end - range.start)
+            .sum::<u64>()
+        + tail.len() as u64;
+    headers.insert(CONTENT_LENGTH, HeaderValue::from(total));
+    headers.insert(
+        CONTENT_TYPE,
+        HeaderValue::from_str(&format!("multipart/byteranges; boundary={}", boundary)).unwrap(),
+    );
+
+    let chunks = part_headers
+        .into_iter()
+        .zip(parts)
+        .flat_map(|(head, part)| vec![Body::from(head), part])
+        .chain(std::iter::once(Body::from(tail)));
+    *response.body_mut() = Body::wrap_stream(stream::iter(chunks).flatten());
+}
+
+fn content_range(range: &Range<u64>, len: u64) -> HeaderValue {
+    let value = format!("bytes {}-{}/{}", range.start, range.end - 1, len);
+    HeaderValue::from_str(&value).unwrap()
+}
+
+fn boundary() -> String {
+    static COUNTER: AtomicU64 = AtomicU64::new(0);
+
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_nanos() as u64;
+
+    format!(
+        "condey-{:016x}",
+        nanos ^ COUNTER.fetch_add(1, Ordering::Relaxed)
+    )
+}
+
+/// Reads a range of a file, opening it only once the body is polled.
+fn file_part(path: PathBuf, range: Range<u64>) -> Body {
+    let open = async move {
+        let mut file = File::open(&path).await?;
+        file.seek(SeekFrom::Start(range.start)).await?;
+
+        Ok::<_, std::io::Error>(ReaderStream::new(file.take(range.end - range.start)))
+    };
+
+    Body::wrap_stream(stream::once(open).try_flatten())
+}
+
+/// Skips through a body to send one range of it.
+struct Slice {
+    body: Body,
+    skip: u64,
+    remaining: u64,
+}
+
+impl Slice {
+    fn new(body: Body, range: Range<u64>) -> Self {
+        Slice {
+            body,
+            skip: range.start,
+            remaining: range.end - range.start,
+        }
+    }
+}
+
+impl Stream for Slice {
+    type Item = Result<Bytes, hyper::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        while self.remaining > 0 {
+            let mut chunk = match ready!(Pin::new(&mut self.body).poll_data(cx)) {
+                Some(Ok(chunk)) => chunk,
+                other => return Poll::Ready(other),
+            };
+
+            let skipped = self.skip.min(chunk.len() as u64);
+            self.skip -= skipped;
+            let _ = chunk.split_to(skipped as usize);
+
+            chunk.truncate(self.remaining.min(chunk.len() as u64) as usize);
+            if !chunk.is_empty() {
+                self.remaining -= chunk.len() as u64;
+                return Poll::Ready(Some(Ok(chunk)));
+            }
+        }
+
+        Poll::Ready(None)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{types::StreamBody, Condey, Route, StaticFiles, TestClient};
+
+    use std::fs;
+
+    const DIGITS: &str = "0123456789";
+
+    #[test]
+    fn parse_range_header() {
+        assert_eq!(
+            parse_ranges("bytes=0-4", 10),
+            Some(Ok(vec![Range { start: 0, end: 5 }]))
+        );
+        assert_eq!(
+            parse_ranges("bytes=-3, 8-", 10),
+            Some(Ok(vec![Range { start: 7, end: 10 }]))
+        );
+        assert_eq!(
+            parse_ranges("bytes=6-7, 0-1, 1-2, 3-3", 10),
+            Some(Ok(vec![0..4, 6..8]))
+        );
+        assert_eq!(
+            parse_ranges("bytes=5-100", 10),
+            Some(Ok(vec![Range { start: 5, end: 10 }]))
+        );
+        assert_eq!(parse_ranges("bytes=10-, 20-30", 10), Some(Err(())));
+        assert_eq!(parse_ranges("bytes=5-4", 10), None);
+        assert_eq!(parse_ranges("bytes=0-6, 3-9", 10), None);
+        assert_eq!(parse_ranges("items=0-4", 10), None);
+    }
+
+    #[tokio::test]
+    async fn single_range() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("digits.txt"), DIGITS).unwrap();
+        let routes = vec![
+            Route::builder()
+                .method(Method::GET)
+                .path("/bytes")
+                .with_handler_fn(|| async { DIGITS.as_bytes().to_vec() }),
+            Route::builder()
+                .method(Method::GET)
+                .path("/stream")
+                .with_handler_fn(|| async {
+                    let chunks = ["012", "3456", "789"]
+                        .iter()
+                        .map(|chunk| Ok::<_, std::io::Error>(Bytes::from(*chunk)))
+                        .collect::<Vec<_>>();
+                    StreamBody::new(stream::iter(chunks)).content_length(10)
+                }),
+        ];
+        let client = TestClient::new(
+            Condey::init()
+                .mount("", routes)
+                .mount("/files", StaticFiles::new(dir.path())),
+        );
+
+        for path in &["/bytes", "/stream", "/files/digits.txt"] {
+            let response = client.get(path).header("range", "bytes=2-5").send().await;
+            response
+                .assert_status(StatusCode::PARTIAL_CONTENT)
+                .assert_header("content-range", "bytes 2-5/10")
+                .assert_header("content-length", "4");
+
+            assert_eq!(response.text().await, "2345", "range of {}", path);
+        }
+    }
+
+    #[tokio::test]
+    async fn multiple_ranges() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("digits.txt"), DIGITS).unwrap();
+        let route = Route::builder()
+            .method(Method::GET)
+            .path("/bytes")
+            .with_handler_fn(|| async { DIGITS.as_bytes().to_vec() });
+        let client = TestClient::new(
+            Condey::init()
+                .mount("", vec![route])
+                .mount("/files", StaticFiles::new(dir.path())),
+        );
+
+        for path in &["/bytes", "/files/digits.txt"] {
+            let response = client
+                .get(path)
+                .header("range", "bytes=0-1, -2")
+                .send()
+                .await;
+            response.assert_status(StatusCode::PARTIAL_CONTENT);
+
+            let content_type = response.header("content-type").unwrap();
+            let boundary = content_type
+                .strip_prefix("multipart/byteranges; boundary=")
+                .unwrap()
+                .to_string();
+            let len = response.header("content-length").unwrap().parse::<usize>();
+            let body = response.text().await;
+            assert_eq!(Ok(body.len()), len);
+
+            let parts = body.split(&format!("--{}", boundary)).collect::<Vec<_>>();
+            assert_eq!(parts.len(), 4);
+            assert!(parts[1].contains("content-range: bytes 0-1/10\r\n\r\n01\r\n"));
+            assert!(parts[2].contains("content-range: bytes 8-9/10\r\n\r\n89\r\n"));
+            assert_eq!(parts[3], "--\r\n");
+        }
+    }
+
+    #[tokio::test]
+    async fn overlapping_ranges() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("digits.txt"), DIGITS).unwrap();
+        let route = Route::builder()
+            .method(Method::GET)
+            .path("/bytes")
+            .with_handler_fn(|| async { DIGITS.as_bytes().to_vec() });
+        let client = TestClient::new(
+            Condey::init()
+                .mount("", vec![route])
+                .mount("/files", StaticFiles::new(dir.path())),
+        );
+        let range = format!("bytes={}", vec!["0-"; MAX_RANGES].join(","));
+
+        for path in &["/bytes", "/files/digits.txt"] {
+            let response = client
+                .get(path)
+                .header("range", range.as_str())
+                .send()
+                .await;
+            response.assert_status(StatusCode::OK);
+
+            assert_eq!(response.text().await, DIGITS);
+        }
+    }
+
+    #[tokio::test]
+    async fn unsatisfiable_range() {
+        let route = Route::builder()
+            .method(Method::GET)
+            .path("/bytes")
+            .with_handler_fn(|| async { DIGITS.as_bytes().to_vec() });
+
+        TestClient::new(Condey::init().mount("", vec![route]))
+            .get("/bytes")
+            .header("range", "bytes=10-")
+            .send()
+            .await
+            .assert_status(StatusCode::RANGE_NOT_SATISFIABLE)
+            .assert_header("content-range", "bytes */10");
+    }
+
+    #[tokio::test]
+    async fn if_range() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("digits.txt"), DIGITS).unwrap();
+        let client = TestClient::new(Condey::init().mount("/files", StaticFiles::new(dir.path())));
+
+        let etag = client
+            .get("/files/digits.txt")
+            .send()
+            .await
+            .assert_header("accept-ranges", "bytes")
+            .header("etag")
+            .unwrap()
+            .to_string();
+
+        client
+            .get("/files/digits.txt")
+            .header("range", "bytes=0-1")
+            .header("if-range", etag.as_str())
+            .send()
+            .await
+            .assert_status(StatusCode::PARTIAL_CONTENT);
+        client
+            .get("/files/digits.txt")
+            .header("range", "bytes=0-1")
+            .header("if-range", "\"outdated\"")
+            .send()
+            .await
+            .assert_status(StatusCode::OK);
+    }
+}
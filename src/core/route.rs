@@ -1,5 +1,12 @@
+use super::cors::Cors;
 use super::handler::Handler;
-use crate::{http::method::Method, HandlerFn};
+use super::service::{HandlerService, LayeredHandler};
+use crate::{
+    http::{method::Method, Request},
+    Body, HandlerFn, Response,
+};
+
+use tower::{BoxError, Layer, Service};
 
 use std::{fmt::Display, marker::PhantomData, sync::Arc};
 
@@ -8,24 +15,70 @@ pub struct Route {
     pub(crate) method: Method,
     pub(crate) path: String,
     pub(crate) handler: Arc<dyn Handler>,
+    pub(crate) body_limit: Option<usize>,
+    pub(crate) cors: Option<Arc<Cors>>,
+    pub(crate) compression: Option<bool>,
 }
 
 impl Route {
+    /// # Panics
+    ///
+    /// When more than one argument of the handler consumes the request body.
     pub fn new<P, H>(method: Method, path: P, handler: H) -> Self
     where
         P: Display,
         H: Handler,
     {
+        let path = path.to_string();
+        assert!(
+            handler.body_extractors() <= 1,
+            "handler of {} {} has more than one argument consuming the request body",
+            method,
+            path
+        );
+
         Route {
             method,
-            path: path.to_string(),
+            path,
             handler: Arc::new(handler),
+            body_limit: None,
+            cors: None,
+            compression: None,
         }
     }
 
     pub fn builder() -> RouteBuilder<AddMethod> {
         RouteBuilder::default()
     }
+
+    /// Applies a CORS policy to this route, overriding mount and app policies.
+    pub fn cors(mut self, cors: Cors) -> Self {
+        self.cors = Some(Arc::new(cors));
+        self
+    }
+
+    /// Turns response compression on or off for this route. Routes compress
+    /// when the app has a [`Compression`](crate::Compression) config by default.
+    pub fn compression(mut self, enable: bool) -> Self {
+        self.compression = Some(enable);
+        self
+    }
+
+    /// Wraps the route handler in a tower layer. Middleware that relies on the
+    /// readiness of the next one, like load shedding over a concurrency limit,
+    /// has to be passed as a single `ServiceBuilder`.
+    pub fn layer<L>(mut self, layer: L) -> Self
+    where
+        L: Layer<HandlerService>,
+        L::Service: Service<Request<Body>, Response = Response> + Clone + Send + 'static,
+        <L::Service as Service<Request<Body>>>::Error: Into<BoxError>,
+        <L::Service as Service<Request<Body>>>::Future: Send + 'static,
+    {
+        let service = layer.layer(HandlerService(self.handler));
+        self.handler = Arc::new(LayeredHandler::new(service));
+
+        self
+    }
 }
 
 pub trait RouteBuilderState {}
@@ -41,6 +94,7 @@ impl RouteBuilderState for WithHandler {}
 pub struct RouteBuilder<T: RouteBuilderState> {
     pub(crate) method: Option<Method>,
     pub(crate) path: Option<String>,
+    pub(crate) body_limit: Option<usize>,
     pub(crate) state: PhantomData<T>,
 }
 
@@ -49,6 +103,7 @@ impl<T: RouteBuilderState> Default for RouteBuilder<T> {
         RouteBuilder {
             method: None,
             path: None,
+            body_limit: None,
             state: PhantomData,
         }
     }
@@ -74,8 +129,16 @@ impl RouteBuilder<AddPath> {
 }
 
 impl RouteBuilder<WithHandler> {
+    /// Overrides [`ServerConfig::body_limit`](crate::ServerConfig::body_limit) for this route.
+    pub fn body_limit(mut self, limit: usize) -> Self {
+        self.body_limit = Some(limit);
+        self
+    }
+
     pub fn with_handler<H: Handler>(self, handler: H) -> Route {
-        Route::new(self.method.unwrap(), self.path.unwrap(), handler)
+        let mut route = Route::new(self.method.unwrap(), self.path.unwrap(), handler);
+        route.body_limit = self.body_limit;
+        route
     }
 
     pub fn with_handler_fn<H, F, P>(self, handler_fn: H) -> Route
@@ -83,6 +146,6 @@ impl RouteBuilder<WithHandler> {
         H: Into<HandlerFn<F, P>>,
         HandlerFn<F, P>: Handler,
     {
-        Route::new(self.method.unwrap(), self.path.unwrap(), handler_fn.into())
+        self.with_handler(handler_fn.into())
     }
 }
@@ -0,0 +1,313 @@
+use super::{cors::Cors, handler::Handler, route::Route};
+use crate::http::Method;
+
+use fnv::FnvHashMap as HashMap;
+use route_recognizer::{Match, Router};
+
+use std::sync::{
+    atomic::{AtomicBool, Ordering},
+    Arc, RwLock,
+};
+
+/// Routes mounted under their prefixes, ready to be swapped into a running app
+/// through a [`RoutesHandle`].
+#[derive(Clone, Default)]
+pub struct RouteTable {
+    routes: Vec<Route>,
+}
+
+impl RouteTable {
+    pub fn new() -> Self {
+        RouteTable::default()
+    }
+
+    pub fn mount(mut self, prefix: &str, paths: impl Into<Vec<Route>>) -> Self {
+        paths.into().into_iter().for_each(|mut route| {
+            let path = format!("{}/{}", prefix, route.path.trim_start_matches('/'));
+            // requests are matched without their trailing slash
+            route.path = path.trim_end_matches('/').to_string();
+            self.routes.push(route);
+        });
+
+        self
+    }
+
+    /// Mounts `paths` with a CORS policy for routes that have none of their own.
+    pub fn mount_with_cors(self, prefix: &str, paths: impl Into<Vec<Route>>, cors: Cors) -> Self {
+        let cors = Arc::new(cors);
+        let paths: Vec<Route> = paths
+            .into()
+            .into_iter()
+            .map(|mut route| {
+                route.cors.get_or_insert_with(|| cors.clone());
+                route
+            })
+            .collect();
+
+        self.mount(prefix, paths)
+    }
+
+    pub(crate) fn compile(self) -> Routers {
+        // TODO: matchit should provide some Result<T,E> API
+        let mut routers: HashMap<_, Router<_>> = HashMap::default();
+        let len = self.routes.len();
+
+        self.routes.into_iter().for_each(|route| {
+            let path = route.path;
+            let method = route.method;
+            let entry = RouteEntry {
+                handler: route.handler,
+                body_limit: route.body_limit,
+                cors: route.cors,
+                compression: route.compression,
+            };
+            tracing::info!("mounting route: {} {}", method, path);
+
+            let node = routers.entry(method).or_default();
+            node.add(&path, entry);
+        });
+
+        Routers { routers, len }
+    }
+}
+
+pub(crate) struct RouteEntry {
+    pub handler: Arc<dyn Handler>,
+    pub body_limit: Option<usize>,
+    pub cors: Option<Arc<Cors>>,
+    pub compression: Option<bool>,
+}
+
+pub(crate) struct Routers {
+    routers: HashMap<Method, Router<RouteEntry>>,
+    len: usize,
+}
+
+impl Routers {
+    /// Falls back to the GET route for HEAD requests without a HEAD route.
+    pub fn recognize(&self, method: &Method, path: &str) -> Option<Match<&RouteEntry>> {
+        let lookup = |method| {
+            self.routers
+                .get(method)
+                .and_then(|node| node.recognize(path).ok())
+        };
+
+        match lookup(method) {
+            None if method == Method::HEAD => lookup(&Method::GET),
+            found => found,
+        }
+    }
+
+    /// Methods `path` can be requested with, including the implicit HEAD and
+    /// OPTIONS. Empty when no route matches.
+    pub fn allowed_methods(&self, path: &str) -> Vec<Method> {
+        let mut methods = self
+            .routers
+            .iter()
+            .filter(|(_, router)| router.recognize(path).is_ok())
+            .map(|(method, _)| method.clone())
+            .collect::<Vec<_>>();
+
+        if methods.is_empty() {
+            return methods;
+        }
+
+        if methods.contains(&Method::GET) && !methods.contains(&Method::HEAD) {
+            methods.push(Method::HEAD);
+        }
+        if !methods.contains(&Method::OPTIONS) {
+            methods.push(Method::OPTIONS);
+        }
+        methods.sort_by(|a, b| a.as_str().cmp(b.as_str()));
+
+        methods
+    }
+}
+
+/// Replaces the route table of a running app. Requests already being handled
+/// finish on the table they started with. A table set before the app is built
+/// takes the place of the mounted routes.
+#[derive(Clone)]
+pub struct RoutesHandle {
+    current: Arc<RwLock<Arc<Routers>>>,
+    replaced: Arc<AtomicBool>,
+}
+
+impl Default for RoutesHandle {
+    fn default() -> Self {
+        RoutesHandle {
+            current: Arc::new(RwLock::new(Arc::new(RouteTable::new().compile()))),
+            replaced: Arc::default(),
+        }
+    }
+}
+
+impl RoutesHandle {
+    pub fn replace(&self, table: RouteTable) {
+        let routers = Arc::new(table.compile());
+        let len = routers.len;
+
+        let mut current = self.current.write().unwrap();
+        self.replaced.store(true, Ordering::SeqCst);
+        let previous = std::mem::replace(&mut *current, routers);
+
+        tracing::info!(
+            "route table replaced: {} routes, previously {}",
+            len,
+            previous.len
+        );
+    }
+
+    /// Installs the mounted routes unless a table was set through `replace` already.
+    pub(crate) fn install(&self, routers: Routers) {
+        let mut current = self.current.write().unwrap();
+        if !self.replaced.load(Ordering::SeqCst) {
+            *current = Arc::new(routers);
+        }
+    }
+
+    pub(crate) fn current(&self) -> Arc<Routers> {
+        self.current.read().unwrap().clone()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{http::StatusCode, Condey, TestClient};
+
+    use std::time::Duration;
+
+    fn route(path: &str, body: &'static str) -> Route {
+        Route::builder()
+            .method(Method::GET)
+            .path(path)
+            .with_handler_fn(move || async move {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+                body.to_string()
+            })
+    }
+
+    #[tokio::test]
+    async fn swap_routes() {
+        let condey = Condey::init().mount("/api", vec![route("/old", "old")]);
+        let handle = condey.routes_handle();
+        let client = TestClient::new(condey);
+
+        let (in_flight, _) = futures::join!(client.get("/api/old").send(), async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+            handle.replace(RouteTable::new().mount("/api", vec![route("/new", "new")]));
+        });
+
+        in_flight.assert_status(StatusCode::OK);
+        assert_eq!(in_flight.text().await, "old");
+
+        client
+            .get("/api/old")
+            .send()
+            .await
+            .assert_status(StatusCode::NOT_FOUND);
+
+        let response = client.get("/api/new").send().await;
+        response.assert_status(StatusCode::OK);
+        assert_eq!(response.text().await, "new");
+    }
+
+    #[tokio::test]
+    async fn replace_before_start() {
+        let condey = Condey::init().mount("/api", vec![route("/old", "old")]);
+        condey
+            .routes_handle()
+            .replace(RouteTable::new().mount("/api", vec![route("/new", "new")]));
+        let client = TestClient::new(condey);
+
+        client
+            .get("/api/old")
+            .send()
+            .await
+            .assert_status(StatusCode::NOT_FOUND);
+        client
+            .get("/api/new")
+            .send()
+            .await
+            .assert_status(StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn head_falls_back_to_get() {
+        let client = TestClient::new(Condey::init().mount("", vec![route("/old", "old")]));
+
+        let response = client.head("/old").send().await;
+        response
+            .assert_status(StatusCode::OK)
+            .assert_header("content-length", "3")
+            .assert_header("content-type", "text/plain");
+        assert!(response.bytes().await.is_empty());
+
+        client
+            .post("/old")
+            .send()
+            .await
+            .assert_status(StatusCode::METHOD_NOT_ALLOWED);
+    }
+
+    #[tokio::test]
+    async fn explicit_head_route() {
+        let head = Route::builder()
+            .method(Method::HEAD)
+            .path("/old")
+            .with_handler_fn(|| async { StatusCode::NO_CONTENT });
+        let client = TestClient::new(Condey::init().mount("", vec![route("/old", "old"), head]));
+
+        client
+            .head("/old")
+            .send()
+            .await
+            .assert_status(StatusCode::NO_CONTENT);
+        client
+            .get("/old")
+            .send()
+            .await
+            .assert_status(StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn allow_header() {
+        let post = Route::builder()
+            .method(Method::POST)
+            .path("/old")
+            .with_handler_fn(|| async { StatusCode::CREATED });
+        let client = TestClient::new(Condey::init().mount("", vec![route("/old", "old"), post]));
+
+        client
+            .options("/old")
+            .send()
+            .await
+            .assert_status(StatusCode::NO_CONTENT)
+            .assert_header("allow", "GET, HEAD, OPTIONS, POST");
+        client
+            .delete("/old")
+            .send()
+            .await
+            .assert_status(StatusCode::METHOD_NOT_ALLOWED)
+            .assert_header("allow", "GET, HEAD, OPTIONS, POST");
+        client
+            .options("/missing")
+            .send()
+            .await
+            .assert_status(StatusCode::NOT_FOUND);
+    }
+
+    #[tokio::test]
+    async fn explicit_options_route() {
+        let options = Route::builder()
+            .method(Method::OPTIONS)
+            .path("/old")
+            .with_handler_fn(|| async { "custom".to_string() });
+        let client = TestClient::new(Condey::init().mount("", vec![route("/old", "old"), options]));
+
+        let response = client.options("/old").send().await;
+        response.assert_status(StatusCode::OK);
+        assert_eq!(response.text().await, "custom");
+    }
+}
@@ -0,0 +1,304 @@
+use super::condey::CondeyService;
+use super::handler::Handler;
+use crate::{
+    http::{Request, Response as HttpResponse},
+    Body, Response,
+};
+
+use futures::{future::BoxFuture, FutureExt, TryFutureExt};
+use hyper::StatusCode;
+use tower::{
+    load_shed::error::Overloaded, timeout::error::Elapsed, util::BoxCloneService, BoxError, Layer,
+    Service, ServiceExt,
+};
+
+use std::{
+    convert::Infallible,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+};
+
+pub(crate) type BoxedService = BoxCloneService<Request<Body>, Response, BoxError>;
+pub(crate) type BoxedLayer = Box<dyn FnOnce(BoxedService) -> BoxedService + Send>;
+
+/// Boxes a tower layer so it can be applied to the app once it is built.
+pub(crate) fn boxed_layer<L>(layer: L) -> BoxedLayer
+where
+    L: Layer<BoxedService> + Send + 'static,
+    L::Service: Service<Request<Body>, Response = Response> + Clone + Send + 'static,
+    <L::Service as Service<Request<Body>>>::Error: Into<BoxError>,
+    <L::Service as Service<Request<Body>>>::Future: Send + 'static,
+{
+    Box::new(move |service| BoxCloneService::new(layer.layer(service).map_err(Into::into)))
+}
+
+/// Turns errors produced by tower middleware into responses.
+fn error_response(err: BoxError) -> Response {
+    let status = if err.is::<Elapsed>() {
+        StatusCode::REQUEST_TIMEOUT
+    } else if err.is::<Overloaded>() {
+        StatusCode::SERVICE_UNAVAILABLE
+    } else {
+        tracing::error!("middleware error: {}", err);
+        StatusCode::INTERNAL_SERVER_ERROR
+    };
+
+    HttpResponse::builder()
+        .status(status)
+        .body(Body::empty())
+        .unwrap()
+}
+
+/// A built [`Condey`](crate::Condey) app with all global layers applied.
+///
+/// Implements `tower::Service<Request<Body>>`, so it can be driven by anything
+/// speaking tower, e.g. hyper or another framework's router.
+pub struct AppService {
+    inner: BoxedService,
+    /// Readiness error of the inner service, answered by the next `call`.
+    not_ready: Option<BoxError>,
+}
+
+impl Clone for AppService {
+    fn clone(&self) -> Self {
+        AppService {
+            inner: self.inner.clone(),
+            not_ready: None,
+        }
+    }
+}
+
+impl AppService {
+    pub(crate) fn new(condey_service: Arc<CondeyService>, layers: Vec<BoxedLayer>) -> Self {
+        let routing = BoxCloneService::new(RoutingService(condey_service));
+        let inner = layers
+            .into_iter()
+            .fold(routing, |service, layer| layer(service));
+
+        AppService {
+            inner,
+            not_ready: None,
+        }
+    }
+}
+
+impl Service<Request<Body>> for AppService {
+    type Response = Response;
+    type Error = Infallible;
+    type Future = BoxFuture<'static, Result<Response, Infallible>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        // readiness errors are answered by `call` without reaching the inner service
+        if let Err(err) = futures::ready!(self.inner.poll_ready(cx)) {
+            self.not_ready = Some(err);
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        if let Some(err) = self.not_ready.take() {
+            return futures::future::ready(Ok(error_response(err))).boxed();
+        }
+
+        self.inner
+            .call(req)
+            .or_else(|err| async move { Ok(error_response(err)) })
+            .boxed()
+    }
+}
+
+#[derive(Clone)]
+struct RoutingService(Arc<CondeyService>);
+
+impl Service<Request<Body>> for RoutingService {
+    type Response = Response;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Response, BoxError>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        self.0
+            .clone()
+            .handle_request(req)
+            .map(|result| match result {
+                Ok(response) => Ok(response),
+                Err(never) => match never {},
+            })
+            .boxed()
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("handler failed")]
+struct HandlerError;
+
+/// Route handler exposed as a tower service, so route layers can wrap it.
+#[derive(Clone)]
+pub struct HandlerService(pub(crate) Arc<dyn Handler>);
+
+impl Service<Request<Body>> for HandlerService {
+    type Response = Response;
+    type Error = BoxError;
+    type Future = BoxFuture<'static, Result<Response, BoxError>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        self.0
+            .handle_request(req)
+            .map_err(|()| BoxError::from(HandlerError))
+            .boxed()
+    }
+}
+
+/// Route handler backed by a layered tower service.
+pub(crate) struct LayeredHandler<S> {
+    service: Mutex<S>,
+}
+
+impl<S> LayeredHandler<S> {
+    pub fn new(service: S) -> Self {
+        LayeredHandler {
+            service: Mutex::new(service),
+        }
+    }
+}
+
+impl<S> Handler for LayeredHandler<S>
+where
+    S: Service<Request<Body>, Response = Response> + Clone + Send + 'static,
+    S::Error: Into<BoxError>,
+    S::Future: Send + 'static,
+{
+    fn handle_request(
+        &self,
+        request: Request<Body>,
+    ) -> Pin<Box<dyn Future<Output = Result<Response, ()>> + Send>> {
+        let service = self.service.lock().unwrap().clone();
+
+        Box::pin(async move {
+            match service.oneshot(request).await {
+                Ok(response) => Ok(response),
+                Err(err) => {
+                    let err = err.into();
+                    if err.is::<HandlerError>() {
+                        Err(())
+                    } else {
+                        Ok(error_response(err))
+                    }
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{http::Method, Condey, Route, TestClient};
+
+    use tower::{
+        limit::ConcurrencyLimitLayer, load_shed::LoadShedLayer, timeout::TimeoutLayer,
+        ServiceBuilder,
+    };
+
+    use std::time::Duration;
+
+    fn slow_route() -> Route {
+        Route::builder()
+            .method(Method::GET)
+            .path("/slow")
+            .with_handler_fn(|| async {
+                tokio::time::sleep(Duration::from_millis(200)).await;
+                "done".to_string()
+            })
+    }
+
+    #[tokio::test]
+    async fn app_is_tower_service() {
+        let app = Condey::init()
+            .mount("", vec![slow_route()])
+            .into_service()
+            .unwrap();
+
+        let request = Request::get("/slow").body(Body::empty()).unwrap();
+        let response = app.oneshot(request).await.unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn global_layer() {
+        let client = TestClient::new(
+            Condey::init()
+                .mount("", vec![slow_route()])
+                .layer(TimeoutLayer::new(Duration::from_millis(10))),
+        );
+
+        client
+            .get("/slow")
+            .send()
+            .await
+            .assert_status(StatusCode::REQUEST_TIMEOUT);
+    }
+
+    #[tokio::test]
+    async fn route_layer() {
+        let shed = slow_route().layer(
+            ServiceBuilder::new()
+                .layer(LoadShedLayer::new())
+                .layer(ConcurrencyLimitLayer::new(1)),
+        );
+        let client = TestClient::new(Condey::init().mount("", vec![shed]));
+
+        let (first, second) = futures::join!(client.get("/slow").send(), async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            client.get("/slow").send().await
+        });
+
+        first.assert_status(StatusCode::OK);
+        second.assert_status(StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[derive(Clone)]
+    struct NeverReady<S>(S);
+
+    impl<S> Service<Request<Body>> for NeverReady<S>
+    where
+        S: Service<Request<Body>, Error = BoxError>,
+    {
+        type Response = S::Response;
+        type Error = BoxError;
+        type Future = S::Future;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+            Poll::Ready(Err("backend gone".into()))
+        }
+
+        fn call(&mut self, _req: Request<Body>) -> Self::Future {
+            panic!("called without being ready")
+        }
+    }
+
+    #[tokio::test]
+    async fn readiness_error() {
+        let client = TestClient::new(
+            Condey::init()
+                .mount("", vec![slow_route()])
+                .layer(tower::layer::layer_fn(NeverReady)),
+        );
+
+        client
+            .get("/slow")
+            .send()
+            .await
+            .assert_status(StatusCode::INTERNAL_SERVER_ERROR);
+    }
+}
@@ -0,0 +1,283 @@
+use super::condey::ServerError;
+
+use futures::{
+    channel::oneshot,
+    future::{self, BoxFuture, Either},
+    Future, FutureExt,
+};
+
+use tokio::{sync::Notify, task::AbortHandle};
+
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+
+pub(crate) type ShutdownSignal = BoxFuture<'static, ()>;
+
+/// Counts live connections, each one holding a [`ConnectionGuard`]. Also the
+/// executor hyper spawns connection tasks on, so they can be aborted once the
+/// drain timed out.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct ConnectionCounter {
+    active: Arc<Active>,
+    tasks: Arc<Mutex<Vec<AbortHandle>>>,
+}
+
+#[derive(Debug, Default)]
+struct Active {
+    count: AtomicUsize,
+    /// Notified whenever the last connection went away.
+    idle: Notify,
+}
+
+impl ConnectionCounter {
+    pub fn track(&self) -> ConnectionGuard {
+        self.active.count.fetch_add(1, Ordering::SeqCst);
+        ConnectionGuard(self.active.clone())
+    }
+
+    pub fn active(&self) -> usize {
+        self.active.count.load(Ordering::SeqCst)
+    }
+
+    /// Runs a task that outlives its HTTP connection, e.g. an upgraded
+    /// WebSocket, as one more connection to drain on shutdown.
+    pub fn spawn<F>(&self, task: F)
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let guard = self.track();
+
+        hyper::rt::Executor::execute(self, async move {
+            task.await;
+            drop(guard);
+        });
+    }
+
+    /// Resolves once no connection is left.
+    pub async fn drained(&self) {
+        loop {
+            let idle = self.active.idle.notified();
+            if self.active() == 0 {
+                return;
+            }
+            idle.await;
+        }
+    }
+
+    /// Aborts every task spawned through the counter that is still running.
+    pub fn abort_all(&self) {
+        self.tasks
+            .lock()
+            .unwrap()
+            .drain(..)
+            .for_each(|task| task.abort());
+    }
+}
+
+impl<F> hyper::rt::Executor<F> for ConnectionCounter
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    fn execute(&self, task: F) {
+        let handle = tokio::spawn(task).abort_handle();
+
+        let mut tasks = self.tasks.lock().unwrap();
+        // forget finished tasks whenever the list would grow, which keeps it amortized O(1)
+        if tasks.len() == tasks.capacity() {
+            tasks.retain(|task| !task.is_finished());
+        }
+        tasks.push(handle);
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct ConnectionGuard(Arc<Active>);
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        if self.0.count.fetch_sub(1, Ordering::SeqCst) == 1 {
+            self.0.idle.notify_waiters();
+        }
+    }
+}
+
+/// Splits the shutdown signal into the future handed to hyper and a receiver
+/// notified once the signal fired.
+pub(crate) fn observe(signal: ShutdownSignal) -> (impl Future<Output = ()>, oneshot::Receiver<()>) {
+    let (tx, rx) = oneshot::channel();
+
+    let signal = signal.map(move |_| {
+        tracing::info!("shutdown signal received, draining connections");
+        let _ = tx.send(());
+    });
+
+    (signal, rx)
+}
+
+/// Drives `server` to completion. Once `signalled` fires the server gets at most
+/// `drain_timeout` to finish in-flight requests and upgraded connections, after
+/// which remaining connections are aborted.
+pub(crate) async fn run_until_drained<S>(
+    server: S,
+    signalled: oneshot::Receiver<()>,
+    drain_timeout: Duration,
+    connections: ConnectionCounter,
+) -> Result<(), ServerError>
+where
+    S: Future<Output = Result<(), ServerError>>,
+{
+    // the server only stops on its own after an error or the shutdown signal,
+    // upgraded connections live on after hyper let go of them
+    let drained = async {
+        server.await?;
+        connections.drained().await;
+        Ok(())
+    };
+    futures::pin_mut!(drained);
+
+    if let Either::Left((result, _)) = future::select(drained.as_mut(), signalled).await {
+        return result;
+    }
+
+    match tokio::time::timeout(drain_timeout, drained).await {
+        Ok(result) => {
+            tracing::info!("all connections drained");
+            result
+        }
+        Err(_) => {
+            let active = connections.active();
+            tracing::warn!(
+                "drain timed out after {:?}, dropping {} connection(s)",
+                drain_timeout,
+                active
+            );
+            connections.abort_all();
+
+            Err(ServerError::DrainTimeoutError {
+                connections: active,
+            })
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn drain_times_out() {
+        let connections = ConnectionCounter::default();
+        let _guard = connections.track();
+
+        let (signal, signalled) = observe(future::ready(()).boxed());
+        let server = async move {
+            signal.await;
+            future::pending::<()>().await;
+            Ok(())
+        };
+
+        let result = run_until_drained(
+            server,
+            signalled,
+            Duration::from_millis(10),
+            connections.clone(),
+        )
+        .await;
+
+        assert!(matches!(
+            result,
+            Err(ServerError::DrainTimeoutError { connections: 1 })
+        ));
+    }
+
+    #[tokio::test]
+    async fn drains_in_time() {
+        let connections = ConnectionCounter::default();
+        let guard = connections.track();
+
+        let (signal, signalled) = observe(future::ready(()).boxed());
+        let server = async move {
+            signal.await;
+            drop(guard);
+            Ok(())
+        };
+
+        let result = run_until_drained(
+            server,
+            signalled,
+            Duration::from_secs(1),
+            connections.clone(),
+        )
+        .await;
+
+        assert!(result.is_ok());
+        assert_eq!(connections.active(), 0);
+    }
+
+    #[tokio::test]
+    async fn abort_stalled_connections() {
+        use crate::{
+            http::Method, types::BodyStream, Condey, Listener, Route, ServerConfig, State,
+        };
+        use tokio::{
+            io::{AsyncReadExt, AsyncWriteExt},
+            net::TcpStream,
+            sync::mpsc,
+        };
+
+        let listener = Listener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().tcp().unwrap();
+        let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+        let (started_tx, mut started_rx) = mpsc::unbounded_channel::<()>();
+
+        let routes = vec![Route::builder()
+            .method(Method::POST)
+            .path("/upload")
+            .with_handler_fn(
+                |body: BodyStream, started: State<mpsc::UnboundedSender<()>>| async move {
+                    started.inner().send(()).unwrap();
+                    let body = hyper::body::to_bytes(body.into_inner()).await;
+                    format!("{:?}", body.map(|body| body.len()))
+                },
+            )];
+        let server = tokio::spawn(
+            Condey::init()
+                .mount("", routes)
+                .app_state(started_tx)
+                .server_config(ServerConfig::default().drain_timeout(Duration::from_millis(50)))
+                .graceful_shutdown(async {
+                    shutdown_rx.await.ok();
+                })
+                .listen_on(listener),
+        );
+
+        // a keep-alive client stalling in the middle of its request body
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(
+                b"POST /upload HTTP/1.1\r\nhost: localhost\r\ncontent-length: 10\r\n\r\n12345",
+            )
+            .await
+            .unwrap();
+        started_rx.recv().await.unwrap();
+
+        shutdown_tx.send(()).unwrap();
+        let result = server.await.unwrap();
+        assert!(matches!(
+            result,
+            Err(ServerError::DrainTimeoutError { connections: 1 })
+        ));
+
+        // the aborted connection task closes the socket instead of waiting for the body
+        let mut rest = vec![];
+        tokio::time::timeout(Duration::from_secs(1), stream.read_to_end(&mut rest))
+            .await
+            .expect("connection still open after the drain timeout")
+            .ok();
+    }
+}
@@ -0,0 +1,329 @@
+use super::{
+    compression::{self, Encoding},
+    range::FileSource,
+};
+use crate::{
+    http::{
+        header::{
+            ACCEPT_ENCODING, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_TYPE, ETAG, LAST_MODIFIED,
+            VARY,
+        },
+        Method, Response, StatusCode,
+    },
+    Body, Handler, Request, Route,
+};
+
+use futures::Future;
+use percent_encoding::percent_decode_str;
+use route_recognizer::Params;
+use tokio::fs::{self, File};
+use tokio_util::io::ReaderStream;
+
+use std::{
+    fs::Metadata,
+    io,
+    path::{Component, Path, PathBuf},
+    pin::Pin,
+    sync::Arc,
+    time::UNIX_EPOCH,
+};
+
+const DEFAULT_INDEX: &str = "index.html";
+
+/// Serves the files of a directory. Mounted like any other set of routes:
+///
+/// ```ignore
+/// Condey::init().mount("/assets", StaticFiles::new("public"))
+/// ```
+///
+/// Paths leaving the directory are answered with `404 Not Found`.
+#[derive(Debug, Clone)]
+pub struct StaticFiles {
+    root: PathBuf,
+    index: Option<String>,
+    precompressed: bool,
+}
+
+impl StaticFiles {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        StaticFiles {
+            root: root.into(),
+            index: Some(DEFAULT_INDEX.to_string()),
+            precompressed: true,
+        }
+    }
+
+    /// File served for directory requests, `index.html` by default.
+    pub fn index_file(mut self, name: impl Into<String>) -> Self {
+        self.index = Some(name.into());
+        self
+    }
+
+    /// Answers directory requests with `404 Not Found`.
+    pub fn no_index(mut self) -> Self {
+        self.index = None;
+        self
+    }
+
+    /// Serves `.br` and `.gz` siblings of a file to clients accepting them,
+    /// on by default.
+    pub fn precompressed(mut self, enable: bool) -> Self {
+        self.precompressed = enable;
+        self
+    }
+
+    /// Maps the path below the mount onto the directory, rejecting segments
+    /// that could leave it.
+    fn resolve(&self, path: &str) -> Option<PathBuf> {
+        let path = percent_decode_str(path).decode_utf8().ok()?;
+        let mut resolved = self.root.clone();
+
+        for segment in path.split('/') {
+            if segment.is_empty() || segment == "." {
+                continue;
+            }
+
+            let mut components = Path::new(segment).components();
+            match (components.next(), components.next()) {
+                (Some(Component::Normal(_)), None) if !segment.contains('\\') => {
+                    resolved.push(segment)
+                }
+                _ => return None,
+            }
+        }
+
+        Some(resolved)
+    }
+
+    async fn serve(&self, req: Request) -> Response<Body> {
+        let path = req
+            .extensions()
+            .get::<Params>()
+            .and_then(|params| params.find("path"))
+            .unwrap_or_default();
+
+        let mut path = match self.resolve(path) {
+            Some(path) => path,
+            None => return status(StatusCode::NOT_FOUND),
+        };
+        let mut metadata = match fs::metadata(&path).await {
+            Ok(metadata) => metadata,
+            Err(err) => return io_error(err),
+        };
+
+        if metadata.is_dir() {
+            match &self.index {
+                Some(index) => path.push(index),
+                None => return status(StatusCode::NOT_FOUND),
+            }
+            metadata = match fs::metadata(&path).await {
+                Ok(metadata) if metadata.is_file() => metadata,
+                Ok(_) => return status(StatusCode::NOT_FOUND),
+                Err(err) => return io_error(err),
+            };
+        }
+
+        let content_type = mime_guess::from_path(&path).first_or_octet_stream();
+
+        let mut encoding = None;
+        let mut varies = false;
+        if self.precompressed {
+            let mut siblings = vec![];
+            for (candidate, extension) in &[(Encoding::Brotli, "br"), (Encoding::Gzip, "gz")] {
+                let mut sibling = path.clone().into_os_string();
+                sibling.push(".");
+                sibling.push(extension);
+
+                if let Ok(sibling_metadata) = fs::metadata(&sibling).await {
+                    if sibling_metadata.is_file() {
+                        siblings.push((*candidate, PathBuf::from(sibling), sibling_metadata));
+                    }
+                }
+            }
+
+            varies = !siblings.is_empty();
+            let candidates = siblings.iter().map(|(enc, ..)| *enc).collect::<Vec<_>>();
+            let accepted = req
+                .headers()
+                .get(ACCEPT_ENCODING)
+                .and_then(|accept| accept.to_str().ok())
+                .and_then(|accept| compression::negotiate(accept, &candidates));
+
+            if let Some((chosen, sibling, sibling_metadata)) = siblings
+                .into_iter()
+                .find(|(candidate, ..)| Some(*candidate) == accepted)
+            {
+                encoding = Some(chosen);
+                path = sibling;
+                metadata = sibling_metadata;
+            }
+        }
+
+        let file = match File::open(&path).await {
+            Ok(file) => file,
+            Err(err) => return io_error(err),
+        };
+
+        let mut response = Response::builder()
+            .header(CONTENT_TYPE, content_type.as_ref())
+            .header(CONTENT_LENGTH, metadata.len())
+            .header(ETAG, etag(&metadata));
+        if let Ok(modified) = metadata.modified() {
+            response = response.header(LAST_MODIFIED, httpdate::fmt_http_date(modified));
+        }
+        if let Some(encoding) = encoding {
+            response = response.header(CONTENT_ENCODING, encoding.as_str());
+        }
+        if varies {
+            response = response.header(VARY, "accept-encoding");
+        }
+
+        response
+            .extension(FileSource(path))
+            .body(Body::wrap_stream(ReaderStream::new(file)))
+            .unwrap()
+    }
+}
+
+impl From<StaticFiles> for Vec<Route> {
+    fn from(files: StaticFiles) -> Self {
+        let handler = StaticHandler(Arc::new(files));
+
+        vec![
+            Route::new(Method::GET, "/", handler.clone()),
+            Route::new(Method::GET, "/*path", handler),
+        ]
+    }
+}
+
+#[derive(Clone)]
+struct StaticHandler(Arc<StaticFiles>);
+
+impl Handler for StaticHandler {
+    fn handle_request(
+        &self,
+        request: Request,
+    ) -> Pin<Box<dyn Future<Output = Result<Response<Body>, ()>> + Send>> {
+        let files = self.0.clone();
+
+        Box::pin(async move { Ok(files.serve(request).await) })
+    }
+}
+
+/// Strong validator built from the size and modification time of a file.
+fn etag(metadata: &Metadata) -> String {
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .unwrap_or_default();
+
+    format!("\"{:x}-{:x}\"", modified.as_nanos(), metadata.len())
+}
+
+/// Failed lookups are `404 Not Found`, e.g. a file used as a directory in the
+/// middle of the path.
+fn io_error(err: io::Error) -> Response<Body> {
+    match err.kind() {
+        io::ErrorKind::PermissionDenied => status(StatusCode::FORBIDDEN),
+        _ => {
+            tracing::debug!("failed to serve static file: {}", err);
+            status(StatusCode::NOT_FOUND)
+        }
+    }
+}
+
+fn status(status: StatusCode) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .body(Body::empty())
+        .unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Condey, TestClient};
+
+    use std::fs;
+
+    #[tokio::test]
+    async fn serve_file() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("app.css"), "h1 { color: red }").unwrap();
+        let client = TestClient::new(Condey::init().mount("/assets", StaticFiles::new(dir.path())));
+
+        let response = client.get("/assets/app.css").send().await;
+        response
+            .assert_status(StatusCode::OK)
+            .assert_header("content-type", "text/css")
+            .assert_header("content-length", "17")
+            .assert_no_header("content-encoding");
+        assert!(response.header("etag").is_some());
+        assert!(response.header("last-modified").is_some());
+
+        assert_eq!(response.text().await, "h1 { color: red }");
+    }
+
+    #[tokio::test]
+    async fn serve_index() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("index.html"), "<h1>Crystal Logic</h1>").unwrap();
+        let client = TestClient::new(Condey::init().mount("/assets", StaticFiles::new(dir.path())));
+
+        for path in &["/assets", "/assets/", "/assets/./"] {
+            let response = client.get(path).send().await;
+            response.assert_header("content-type", "text/html");
+
+            assert_eq!(response.text().await, "<h1>Crystal Logic</h1>");
+        }
+    }
+
+    #[tokio::test]
+    async fn serve_precompressed() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("app.css"), "h1 { color: red }").unwrap();
+        fs::write(dir.path().join("app.css.gz"), "gzipped").unwrap();
+        let client = TestClient::new(Condey::init().mount("/assets", StaticFiles::new(dir.path())));
+
+        let response = client
+            .get("/assets/app.css")
+            .header("accept-encoding", "br, gzip")
+            .send()
+            .await;
+        response
+            .assert_header("content-type", "text/css")
+            .assert_header("content-encoding", "gzip")
+            .assert_header("vary", "accept-encoding");
+
+        assert_eq!(response.text().await, "gzipped");
+    }
+
+    #[tokio::test]
+    async fn block_traversal() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("public/css")).unwrap();
+        fs::write(
+            dir.path().join("public/index.html"),
+            "<h1>Crystal Logic</h1>",
+        )
+        .unwrap();
+        fs::write(dir.path().join("secret"), "secret").unwrap();
+        let public = StaticFiles::new(dir.path().join("public"));
+        let client = TestClient::new(Condey::init().mount("/assets", public));
+
+        for path in &[
+            "/assets/../secret",
+            "/assets/%2e%2e/secret",
+            "/assets/css/..%2f..%2fsecret",
+            "/assets/missing.css",
+            "/assets/index.html/app.css",
+        ] {
+            client
+                .get(path)
+                .send()
+                .await
+                .assert_status(StatusCode::NOT_FOUND);
+        }
+    }
+}
@@ -0,0 +1,291 @@
+use super::{condey::Condey, service::AppService};
+use crate::{
+    http::{
+        header::{HeaderName, CONTENT_TYPE},
+        HeaderMap, HeaderValue, Method, Request, StatusCode,
+    },
+    Body, Response,
+};
+
+use hyper::body::Bytes;
+use serde::{de::DeserializeOwned, Serialize};
+use tower::ServiceExt;
+
+use std::{convert::TryFrom, fmt::Debug};
+
+/// Dispatches requests through a [`Condey`] app in memory, without binding a socket.
+///
+/// ```ignore
+/// let client = TestClient::new(Condey::init().mount("/api", routes));
+///
+/// client
+///     .get("/api/albums")
+///     .send()
+///     .await
+///     .assert_status(StatusCode::OK);
+/// ```
+#[derive(Clone)]
+pub struct TestClient {
+    service: AppService,
+}
+
+impl TestClient {
+    /// Builds the app, including its layers, the same way `listen_at` does.
+    /// Panics when the app is invalid.
+    pub fn new(condey: Condey) -> Self {
+        let service = condey.into_service().expect("invalid Condey app");
+
+        TestClient { service }
+    }
+
+    pub fn get(&self, uri: &str) -> TestRequest {
+        self.request(Method::GET, uri)
+    }
+
+    pub fn post(&self, uri: &str) -> TestRequest {
+        self.request(Method::POST, uri)
+    }
+
+    pub fn put(&self, uri: &str) -> TestRequest {
+        self.request(Method::PUT, uri)
+    }
+
+    pub fn patch(&self, uri: &str) -> TestRequest {
+        self.request(Method::PATCH, uri)
+    }
+
+    pub fn delete(&self, uri: &str) -> TestRequest {
+        self.request(Method::DELETE, uri)
+    }
+
+    pub fn head(&self, uri: &str) -> TestRequest {
+        self.request(Method::HEAD, uri)
+    }
+
+    pub fn options(&self, uri: &str) -> TestRequest {
+        self.request(Method::OPTIONS, uri)
+    }
+
+    pub fn request(&self, method: Method, uri: &str) -> TestRequest {
+        let mut request = Request::new(Body::empty());
+        *request.method_mut() = method;
+        *request.uri_mut() = uri.parse().expect("invalid request URI");
+
+        TestRequest {
+            client: self.clone(),
+            request,
+        }
+    }
+
+    /// Sends a prepared request through the app.
+    pub async fn send(&self, request: Request<Body>) -> TestResponse {
+        let response = match self.service.clone().oneshot(request).await {
+            Ok(response) => response,
+            Err(never) => match never {},
+        };
+
+        TestResponse { response }
+    }
+}
+
+pub struct TestRequest {
+    client: TestClient,
+    request: Request<Body>,
+}
+
+impl TestRequest {
+    pub fn header<K, V>(mut self, name: K, value: V) -> Self
+    where
+        HeaderName: TryFrom<K>,
+        <HeaderName as TryFrom<K>>::Error: Debug,
+        HeaderValue: TryFrom<V>,
+        <HeaderValue as TryFrom<V>>::Error: Debug,
+    {
+        let name = HeaderName::try_from(name).expect("invalid header name");
+        let value = HeaderValue::try_from(value).expect("invalid header value");
+        self.request.headers_mut().append(name, value);
+
+        self
+    }
+
+    pub fn body(mut self, body: impl Into<Body>) -> Self {
+        *self.request.body_mut() = body.into();
+        self
+    }
+
+    /// Serializes `json` as the request body and sets `Content-Type: application/json`.
+    pub fn json<T: Serialize>(self, json: &T) -> Self {
+        let body = serde_json::to_vec(json).expect("failed to serialize JSON body");
+
+        self.header(CONTENT_TYPE, "application/json").body(body)
+    }
+
+    /// Serializes `form` as the request body and sets `Content-Type: application/x-www-form-urlencoded`.
+    pub fn form<T: Serialize>(self, form: &T) -> Self {
+        let body = serde_urlencoded::to_string(form).expect("failed to serialize form body");
+
+        self.header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+            .body(body)
+    }
+
+    pub async fn send(self) -> TestResponse {
+        self.client.send(self.request).await
+    }
+}
+
+#[derive(Debug)]
+pub struct TestResponse {
+    response: Response,
+}
+
+impl TestResponse {
+    pub fn status(&self) -> StatusCode {
+        self.response.status()
+    }
+
+    pub fn headers(&self) -> &HeaderMap {
+        self.response.headers()
+    }
+
+    pub fn header(&self, name: impl AsRef<str>) -> Option<&str> {
+        self.response
+            .headers()
+            .get(name.as_ref())
+            .and_then(|value| value.to_str().ok())
+    }
+
+    pub fn assert_status(&self, status: StatusCode) -> &Self {
+        assert_eq!(self.status(), status, "unexpected response status");
+        self
+    }
+
+    pub fn assert_header(&self, name: impl AsRef<str>, value: &str) -> &Self {
+        let name = name.as_ref();
+        assert_eq!(
+            self.header(name),
+            Some(value),
+            "unexpected `{}` header",
+            name
+        );
+        self
+    }
+
+    pub fn assert_no_header(&self, name: impl AsRef<str>) -> &Self {
+        let name = name.as_ref();
+        assert!(
+            self.headers().get(name).is_none(),
+            "unexpected `{}` header",
+            name
+        );
+        self
+    }
+
+    pub async fn bytes(self) -> Bytes {
+        hyper::body::to_bytes(self.response.into_body())
+            .await
+            .expect("failed to read response body")
+    }
+
+    pub async fn text(self) -> String {
+        String::from_utf8(self.bytes().await.to_vec()).expect("response body is not UTF-8")
+    }
+
+    pub async fn json<T: DeserializeOwned>(self) -> T {
+        serde_json::from_slice(&self.bytes().await).expect("response body is not valid JSON")
+    }
+
+    pub fn into_inner(self) -> Response {
+        self.response
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        types::{Json, Path},
+        Route, State,
+    };
+
+    use serde::Deserialize;
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq)]
+    struct Album {
+        band: String,
+        name: String,
+    }
+
+    fn app() -> Condey {
+        let routes = vec![
+            Route::builder()
+                .method(Method::GET)
+                .path("/greet/:name")
+                .with_handler_fn(
+                    |Path((name,)): Path<(String,)>, greeting: State<&'static str>| async move {
+                        format!("{}, {}!", greeting.inner(), name)
+                    },
+                ),
+            Route::builder()
+                .method(Method::POST)
+                .path("/albums")
+                .with_handler_fn(|album: Json<Album>| async move { album }),
+        ];
+
+        Condey::init().mount("/api", routes).app_state("Hello")
+    }
+
+    #[tokio::test]
+    async fn route_with_state() {
+        let client = TestClient::new(app());
+
+        let response = client.get("/api/greet/Condey").send().await;
+        response
+            .assert_status(StatusCode::OK)
+            .assert_header("content-type", "text/plain");
+
+        assert_eq!(response.text().await, "Hello, Condey!");
+    }
+
+    #[tokio::test]
+    async fn json_roundtrip() {
+        let client = TestClient::new(app());
+        let album = Album {
+            band: "Manilla Road".to_string(),
+            name: "Crystal Logic".to_string(),
+        };
+
+        let response = client.post("/api/albums").json(&album).send().await;
+        response.assert_status(StatusCode::OK);
+
+        assert_eq!(response.json::<Album>().await, album);
+    }
+
+    #[tokio::test]
+    async fn interceptor_response() {
+        let client = TestClient::new(app());
+
+        client
+            .post("/api/albums")
+            .body("{\"band\": 1}")
+            .send()
+            .await
+            .assert_status(StatusCode::UNPROCESSABLE_ENTITY)
+            .assert_header("content-type", "application/json");
+    }
+
+    #[tokio::test]
+    async fn not_found_and_method_not_allowed() {
+        let client = TestClient::new(app());
+
+        client
+            .get("/api/missing")
+            .send()
+            .await
+            .assert_status(StatusCode::NOT_FOUND);
+        client
+            .delete("/api/albums")
+            .send()
+            .await
+            .assert_status(StatusCode::METHOD_NOT_ALLOWED);
+    }
+}
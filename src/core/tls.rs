@@ -0,0 +1,401 @@
+use super::{
+    condey::ServerError,
+    connection::{ConnectInfo, Connected},
+};
+use crate::http::Extensions;
+
+use futures::ready;
+use hyper::server::accept::Accept;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_rustls::{
+    rustls::{
+        crypto::ring,
+        pki_types::{CertificateDer, PrivateKeyDer},
+        ServerConfig as RustlsConfig, ServerConnection,
+    },
+    server, TlsAcceptor,
+};
+
+use std::{
+    fmt,
+    future::Future,
+    io,
+    path::Path,
+    pin::Pin,
+    sync::{Arc, OnceLock},
+    task::{Context, Poll},
+};
+
+/// Certificate chain and private key used to terminate TLS connections.
+#[derive(Clone)]
+pub struct TlsConfig {
+    cert_chain: Vec<CertificateDer<'static>>,
+    key: Arc<PrivateKeyDer<'static>>,
+}
+
+impl fmt::Debug for TlsConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TlsConfig")
+            .field("cert_chain", &self.cert_chain.len())
+            .finish()
+    }
+}
+
+impl TlsConfig {
+    /// Builds the config from PEM encoded certificate chain and private key.
+    pub fn from_pem(cert_chain: &[u8], key: &[u8]) -> Result<Self, ServerError> {
+        let cert_chain = rustls_pemfile::certs(&mut &*cert_chain)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|err| ServerError::CertificateError(err.to_string()))?;
+
+        if cert_chain.is_empty() {
+            return Err(ServerError::CertificateError(
+                "no certificates found".to_string(),
+            ));
+        }
+
+        let key = rustls_pemfile::private_key(&mut &*key)
+            .map_err(|err| ServerError::PrivateKeyError(err.to_string()))?
+            .ok_or_else(|| ServerError::PrivateKeyError("no private key found".to_string()))?;
+
+        Ok(TlsConfig {
+            cert_chain,
+            key: Arc::new(key),
+        })
+    }
+
+    /// Reads PEM encoded certificate chain and private key from the filesystem.
+    pub fn from_pem_files(
+        cert_chain: impl AsRef<Path>,
+        key: impl AsRef<Path>,
+    ) -> Result<Self, ServerError> {
+        let cert_chain = std::fs::read(cert_chain)?;
+        let key = std::fs::read(key)?;
+
+        Self::from_pem(&cert_chain, &key)
+    }
+
+    pub(crate) fn acceptor(&self) -> Result<TlsAcceptor, ServerError> {
+        let mut config = RustlsConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()?
+            .with_no_client_auth()
+            .with_single_cert(self.cert_chain.clone(), self.key.clone_key())?;
+
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+
+        Ok(TlsAcceptor::from(Arc::new(config)))
+    }
+}
+
+/// Accepts connections and wraps them in a lazily handshaking TLS stream,
+/// so a slow handshake never blocks the accept loop.
+pub(crate) struct TlsIncoming<I> {
+    incoming: I,
+    acceptor: TlsAcceptor,
+}
+
+impl<I> TlsIncoming<I> {
+    pub fn new(incoming: I, acceptor: TlsAcceptor) -> Self {
+        TlsIncoming { incoming, acceptor }
+    }
+}
+
+impl<I> Accept for TlsIncoming<I>
+where
+    I: Accept + Unpin,
+    I::Conn: AsyncRead + AsyncWrite + Connected + Unpin,
+{
+    type Conn = TlsStream<I::Conn>;
+    type Error = I::Error;
+
+    fn poll_accept(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
+        let this = &mut *self;
+
+        match ready!(Pin::new(&mut this.incoming).poll_accept(cx)) {
+            Some(Ok(stream)) => Poll::Ready(Some(Ok(TlsStream::new(&this.acceptor, stream)))),
+            Some(Err(err)) => Poll::Ready(Some(Err(err))),
+            None => Poll::Ready(None),
+        }
+    }
+}
+
+enum TlsState<IO> {
+    Handshaking(tokio_rustls::Accept<IO>),
+    Streaming(server::TlsStream<IO>),
+}
+
+/// Parameters negotiated during the TLS handshake.
+#[derive(Debug, Clone)]
+pub struct TlsInfo {
+    server_name: Option<String>,
+    alpn_protocol: Option<Vec<u8>>,
+    protocol_version: Option<&'static str>,
+    cipher_suite: Option<&'static str>,
+}
+
+impl TlsInfo {
+    fn new(connection: &ServerConnection) -> Self {
+        TlsInfo {
+            server_name: connection.server_name().map(str::to_string),
+            alpn_protocol: connection.alpn_protocol().map(<[u8]>::to_vec),
+            protocol_version: connection
+                .protocol_version()
+                .and_then(|version| version.as_str()),
+            cipher_suite: connection
+                .negotiated_cipher_suite()
+                .and_then(|suite| suite.suite().as_str()),
+        }
+    }
+
+    /// Server name the client asked for through SNI.
+    pub fn server_name(&self) -> Option<&str> {
+        self.server_name.as_deref()
+    }
+
+    pub fn alpn_protocol(&self) -> Option<&[u8]> {
+        self.alpn_protocol.as_deref()
+    }
+
+    /// e.g. `TLSv1_3`
+    pub fn protocol_version(&self) -> Option<&str> {
+        self.protocol_version
+    }
+
+    /// e.g. `TLS13_AES_256_GCM_SHA384`
+    pub fn cipher_suite(&self) -> Option<&str> {
+        self.cipher_suite
+    }
+}
+
+#[derive(Clone)]
+pub(crate) struct TlsConnInfo<T> {
+    inner: T,
+    tls: Arc<OnceLock<TlsInfo>>,
+}
+
+pub(crate) struct TlsStream<IO: Connected> {
+    state: TlsState<IO>,
+    info: TlsConnInfo<IO::Info>,
+}
+
+impl<IO> TlsStream<IO>
+where
+    IO: AsyncRead + AsyncWrite + Connected + Unpin,
+{
+    pub fn new(acceptor: &TlsAcceptor, io: IO) -> Self {
+        TlsStream {
+            info: TlsConnInfo {
+                inner: io.connect_info(),
+                tls: Arc::default(),
+            },
+            state: TlsState::Handshaking(acceptor.accept(io)),
+        }
+    }
+
+    fn poll_handshake(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        if let TlsState::Handshaking(accept) = &mut self.state {
+            match ready!(Pin::new(accept).poll(cx)) {
+                Ok(stream) => {
+                    let _ = self.info.tls.set(TlsInfo::new(stream.get_ref().1));
+                    self.state = TlsState::Streaming(stream);
+                }
+                Err(err) => {
+                    tracing::debug!("TLS handshake failed: {}", err);
+                    return Poll::Ready(Err(err));
+                }
+            }
+        }
+
+        Poll::Ready(Ok(()))
+    }
+
+    fn stream(&mut self) -> &mut server::TlsStream<IO> {
+        match &mut self.state {
+            TlsState::Streaming(stream) => stream,
+            TlsState::Handshaking(_) => unreachable!("TLS handshake not finished"),
+        }
+    }
+}
+
+impl<IO: Connected> Connected for TlsStream<IO> {
+    type Info = TlsConnInfo<IO::Info>;
+
+    fn connect_info(&self) -> Self::Info {
+        self.info.clone()
+    }
+
+    fn insert_info(info: &Self::Info, extensions: &mut Extensions) {
+        IO::insert_info(&info.inner, extensions);
+
+        if let (Some(tls), Some(connect_info)) =
+            (info.tls.get(), extensions.get_mut::<ConnectInfo>())
+        {
+            connect_info.set_tls(tls.clone());
+        }
+    }
+}
+
+impl<IO> AsyncRead for TlsStream<IO>
+where
+    IO: AsyncRead + AsyncWrite + Connected + Unpin,
+{
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        ready!(self.poll_handshake(cx))?;
+        Pin::new(self.stream()).poll_read(cx, buf)
+    }
+}
+
+impl<IO> AsyncWrite for TlsStream<IO>
+where
+    IO: AsyncRead + AsyncWrite + Connected + Unpin,
+{
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        ready!(self.poll_handshake(cx))?;
+        Pin::new(self.stream()).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match &mut self.state {
+            TlsState::Handshaking(_) => Poll::Ready(Ok(())),
+            TlsState::Streaming(stream) => Pin::new(stream).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        match &mut self.state {
+            TlsState::Handshaking(_) => Poll::Ready(Ok(())),
+            TlsState::Streaming(stream) => Pin::new(stream).poll_shutdown(cx),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        http::{Method, StatusCode, Version},
+        Body, Condey, Listener, Route,
+    };
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    };
+    use tokio_rustls::{
+        rustls::{pki_types::ServerName, ClientConfig, RootCertStore},
+        TlsConnector,
+    };
+
+    use std::convert::TryFrom;
+
+    #[test]
+    fn reject_invalid_pem() {
+        let result = TlsConfig::from_pem(b"not a certificate", b"not a key");
+        assert!(matches!(result, Err(ServerError::CertificateError(_))));
+    }
+
+    #[test]
+    fn reject_missing_key() {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+        let result = TlsConfig::from_pem(cert.cert.pem().as_bytes(), b"");
+        assert!(matches!(result, Err(ServerError::PrivateKeyError(_))));
+    }
+
+    async fn connect_tls(alpn: &[&[u8]]) -> tokio_rustls::client::TlsStream<TcpStream> {
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".into()]).unwrap();
+        let tls = TlsConfig::from_pem(
+            cert.cert.pem().as_bytes(),
+            cert.key_pair.serialize_pem().as_bytes(),
+        )
+        .unwrap();
+
+        let routes = vec![Route::builder()
+            .method(Method::GET)
+            .path("/hello")
+            .with_handler_fn(|info: ConnectInfo| async move {
+                match info.tls() {
+                    Some(tls) => format!("Hello, {}!", tls.server_name().unwrap_or("TLS")),
+                    None => "Hello, cleartext!".to_string(),
+                }
+            })];
+
+        let listener = Listener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().tcp().unwrap();
+
+        tokio::spawn(
+            Condey::init()
+                .mount("", routes)
+                .listen_on(listener.tls(tls)),
+        );
+
+        let mut roots = RootCertStore::empty();
+        roots.add(cert.cert.der().clone()).unwrap();
+
+        let mut config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        config.alpn_protocols = alpn.iter().map(|proto| proto.to_vec()).collect();
+
+        let tcp = TcpStream::connect(addr).await.unwrap();
+        TlsConnector::from(Arc::new(config))
+            .connect(ServerName::try_from("localhost").unwrap(), tcp)
+            .await
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn serve_https() {
+        let mut stream = connect_tls(&[b"http/1.1"]).await;
+
+        assert_eq!(stream.get_ref().1.alpn_protocol(), Some(&b"http/1.1"[..]));
+
+        stream
+            .write_all(b"GET /hello HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n")
+            .await
+            .unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with("Hello, localhost!"));
+    }
+
+    #[tokio::test]
+    async fn negotiate_h2() {
+        let stream = connect_tls(&[b"h2", b"http/1.1"]).await;
+
+        assert_eq!(stream.get_ref().1.alpn_protocol(), Some(&b"h2"[..]));
+
+        let (mut sender, connection) = hyper::client::conn::Builder::new()
+            .http2_only(true)
+            .handshake::<_, Body>(stream)
+            .await
+            .unwrap();
+        tokio::spawn(connection);
+
+        let request = hyper::Request::get("https://localhost/hello")
+            .body(Body::empty())
+            .unwrap();
+        let response = sender.send_request(request).await.unwrap();
+
+        assert_eq!(response.version(), Version::HTTP_2);
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(&body[..], b"Hello, localhost!");
+    }
+}
@@ -0,0 +1,355 @@
+use super::{
+    connection::{ConnectInfo, Connected},
+    listener::ListenAddr,
+};
+use crate::{http::Extensions, FromRequest, Interceptor, Request};
+
+use anyhow::anyhow;
+use futures::ready;
+use hyper::{server::accept::Accept, StatusCode};
+use tokio::{
+    net::{
+        unix::{self, UCred},
+        UnixListener, UnixStream,
+    },
+    time::Sleep,
+};
+
+use std::{
+    future::Future,
+    io,
+    os::unix::{fs::FileTypeExt, fs::PermissionsExt, net as std_net},
+    path::{Path, PathBuf},
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+/// Pause before accepting again after running out of resources, e.g. file
+/// descriptors.
+const ACCEPT_BACKOFF: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone)]
+enum UnixPath {
+    Filesystem(PathBuf),
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    Abstract(Vec<u8>),
+}
+
+/// Address and options of a Unix domain socket to serve on.
+#[derive(Debug, Clone)]
+pub struct UnixSocket {
+    path: UnixPath,
+    mode: Option<u32>,
+}
+
+impl UnixSocket {
+    /// Socket bound to a filesystem path. A stale socket file left behind by a
+    /// previous process is removed before binding.
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        UnixSocket {
+            path: UnixPath::Filesystem(path.as_ref().to_path_buf()),
+            mode: None,
+        }
+    }
+
+    /// Socket bound in the Linux abstract namespace.
+    #[cfg(any(target_os = "linux", target_os = "android"))]
+    pub fn abstract_name(name: impl AsRef<[u8]>) -> Self {
+        UnixSocket {
+            path: UnixPath::Abstract(name.as_ref().to_vec()),
+            mode: None,
+        }
+    }
+
+    /// Permission bits applied to the socket file, e.g. `0o660`.
+    /// Ignored for abstract sockets.
+    ///
+    /// The socket is created with no permissions and opened up once bound, so it
+    /// never accepts connections with looser ones. The process umask is changed
+    /// for the duration of the `bind` call to do so.
+    pub fn permissions(mut self, mode: u32) -> Self {
+        self.mode = Some(mode);
+        self
+    }
+
+    pub(crate) fn bind(&self) -> io::Result<UnixIncoming> {
+        let listener = match &self.path {
+            UnixPath::Filesystem(path) => {
+                remove_stale_socket(path)?;
+
+                match self.mode {
+                    Some(mode) => {
+                        let listener = bind_private(path)?;
+                        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+                        listener
+                    }
+                    None => std_net::UnixListener::bind(path)?,
+                }
+            }
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            UnixPath::Abstract(name) => {
+                #[cfg(target_os = "android")]
+                use std::os::android::net::SocketAddrExt;
+                #[cfg(target_os = "linux")]
+                use std::os::linux::net::SocketAddrExt;
+
+                let addr = std_net::SocketAddr::from_abstract_name(name)?;
+                std_net::UnixListener::bind_addr(&addr)?
+            }
+        };
+
+        listener.set_nonblocking(true)?;
+
+        Ok(UnixIncoming {
+            listener: UnixListener::from_std(listener)?,
+            cleanup: match &self.path {
+                UnixPath::Filesystem(path) => Some(path.clone()),
+                #[cfg(any(target_os = "linux", target_os = "android"))]
+                UnixPath::Abstract(_) => None,
+            },
+            timeout: None,
+        })
+    }
+}
+
+/// Binds with a umask masking every permission bit, so nobody but root can
+/// connect before the requested permissions are applied.
+fn bind_private(path: &Path) -> io::Result<std_net::UnixListener> {
+    // SAFETY: umask only swaps the file mode creation mask of the process
+    let previous = unsafe { libc::umask(0o777) };
+    let listener = std_net::UnixListener::bind(path);
+    unsafe { libc::umask(previous) };
+
+    listener
+}
+
+fn remove_stale_socket(path: &Path) -> io::Result<()> {
+    let metadata = match std::fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(err) => return Err(err),
+    };
+
+    if !metadata.file_type().is_socket() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} exists and is not a socket", path.display()),
+        ));
+    }
+
+    if std_net::UnixStream::connect(path).is_ok() {
+        return Err(io::Error::new(
+            io::ErrorKind::AddrInUse,
+            format!("{} is in use by another process", path.display()),
+        ));
+    }
+
+    tracing::info!("removing stale socket {}", path.display());
+    std::fs::remove_file(path)
+}
+
+pub(crate) struct UnixIncoming {
+    listener: UnixListener,
+    cleanup: Option<PathBuf>,
+    timeout: Option<Pin<Box<Sleep>>>,
+}
+
+impl UnixIncoming {
+    pub fn local_addr(&self) -> io::Result<Option<PathBuf>> {
+        let addr = self.listener.local_addr()?;
+
+        Ok(addr.as_pathname().map(Path::to_path_buf))
+    }
+}
+
+impl From<UnixListener> for UnixIncoming {
+    fn from(listener: UnixListener) -> Self {
+        UnixIncoming {
+            listener,
+            cleanup: None,
+            timeout: None,
+        }
+    }
+}
+
+impl Accept for UnixIncoming {
+    type Conn = UnixStream;
+    type Error = io::Error;
+
+    fn poll_accept(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
+        if let Some(timeout) = &mut self.timeout {
+            ready!(timeout.as_mut().poll(cx));
+            self.timeout = None;
+        }
+
+        loop {
+            match ready!(self.listener.poll_accept(cx)) {
+                Ok((stream, _)) => return Poll::Ready(Some(Ok(stream))),
+                // the peer went away before the connection was accepted
+                Err(err) if is_connection_error(&err) => {
+                    tracing::debug!("accepted connection already errored: {}", err);
+                }
+                Err(err) => {
+                    tracing::error!("accept error: {}", err);
+
+                    let mut timeout = Box::pin(tokio::time::sleep(ACCEPT_BACKOFF));
+                    if timeout.as_mut().poll(cx).is_pending() {
+                        self.timeout = Some(timeout);
+                        return Poll::Pending;
+                    }
+                }
+            }
+        }
+    }
+}
+
+fn is_connection_error(err: &io::Error) -> bool {
+    matches!(
+        err.kind(),
+        io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::ConnectionReset
+    )
+}
+
+impl Drop for UnixIncoming {
+    fn drop(&mut self) {
+        if let Some(path) = self.cleanup.take() {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// Identity of the process on the other end of a Unix domain socket.
+#[derive(Debug, Clone)]
+pub struct UnixPeer {
+    cred: Option<UCred>,
+    addr: Option<PathBuf>,
+    local: Option<PathBuf>,
+}
+
+impl UnixPeer {
+    pub fn uid(&self) -> Option<u32> {
+        self.cred.map(|cred| cred.uid())
+    }
+
+    pub fn gid(&self) -> Option<u32> {
+        self.cred.map(|cred| cred.gid())
+    }
+
+    pub fn pid(&self) -> Option<i32> {
+        self.cred.and_then(|cred| cred.pid())
+    }
+
+    /// Path the peer socket is bound to, usually `None` for clients.
+    pub fn addr(&self) -> Option<&Path> {
+        self.addr.as_deref()
+    }
+}
+
+impl Connected for UnixStream {
+    type Info = UnixPeer;
+
+    fn connect_info(&self) -> Self::Info {
+        UnixPeer {
+            cred: self.peer_cred().ok(),
+            addr: self
+                .peer_addr()
+                .ok()
+                .as_ref()
+                .and_then(unix::SocketAddr::as_pathname)
+                .map(Path::to_path_buf),
+            local: self
+                .local_addr()
+                .ok()
+                .as_ref()
+                .and_then(unix::SocketAddr::as_pathname)
+                .map(Path::to_path_buf),
+        }
+    }
+
+    fn insert_info(info: &Self::Info, extensions: &mut Extensions) {
+        extensions.insert(ConnectInfo::new(None, ListenAddr::Unix(info.local.clone())));
+        extensions.insert(info.clone());
+    }
+}
+
+#[async_trait::async_trait]
+impl<'r> FromRequest<'r> for UnixPeer {
+    type Error = anyhow::Error;
+
+    async fn from_request(request: &'r Request) -> Result<Self, Self::Error> {
+        request
+            .extensions()
+            .get::<UnixPeer>()
+            .cloned()
+            .ok_or_else(|| anyhow!("request was not received over a Unix socket"))
+    }
+
+    fn default_interceptor() -> Box<dyn Interceptor> {
+        Box::new(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{http::Method, Condey, Route};
+
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn serve_unix() {
+        let path = std::env::temp_dir().join(format!("condey-{}.sock", std::process::id()));
+
+        // leave a stale socket file behind
+        drop(std_net::UnixListener::bind(&path).unwrap());
+
+        let routes = vec![Route::builder()
+            .method(Method::GET)
+            .path("/whoami")
+            .with_handler_fn(|peer: UnixPeer| async move { format!("{}", peer.uid().unwrap()) })];
+
+        tokio::spawn(
+            Condey::init()
+                .mount("", routes)
+                .listen_unix(UnixSocket::new(&path).permissions(0o600)),
+        );
+        tokio::time::sleep(Duration::from_millis(100)).await;
+
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        let mut stream = UnixStream::connect(&path).await.unwrap();
+        let uid = stream.peer_cred().unwrap().uid();
+
+        stream
+            .write_all(b"GET /whoami HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n")
+            .await
+            .unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK"));
+        assert!(response.ends_with(&uid.to_string()));
+    }
+
+    #[test]
+    fn refuse_socket_in_use() {
+        let path = std::env::temp_dir().join(format!("condey-busy-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let _listener = std_net::UnixListener::bind(&path).unwrap();
+
+        let err = remove_stale_socket(&path).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::AddrInUse);
+
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,17 +1,31 @@
 mod core;
 pub mod types;
 
-pub use self::core::condey::Condey;
+pub use self::core::compression::Compression;
+pub use self::core::condey::{Condey, ServerError};
+pub use self::core::config::ServerConfig;
+pub use self::core::connection::ConnectInfo;
+pub use self::core::cors::Cors;
 pub use self::core::from_body::FromBody;
 pub use self::core::from_request::FromRequest;
 pub use self::core::handler::{Handler, HandlerFn};
 pub use self::core::interceptor::Interceptor;
+pub use self::core::listener::{ListenAddr, Listener};
 pub use self::core::param::{FromPathParam, FromPathParamError};
+pub use self::core::proxy::{ClientAddr, ProxyProtocol};
 pub use self::core::request::Request;
 pub use self::core::response::{Responder, Response};
 pub use self::core::route::Route;
+pub use self::core::route_table::{RouteTable, RoutesHandle};
+pub use self::core::service::{AppService, HandlerService};
 pub use self::core::state::State;
+pub use self::core::static_files::StaticFiles;
+pub use self::core::test_client::{TestClient, TestRequest, TestResponse};
+pub use self::core::tls::{TlsConfig, TlsInfo};
+#[cfg(unix)]
+pub use self::core::unix::{UnixPeer, UnixSocket};
 
 pub use hyper;
 pub use hyper::http;
 pub use hyper::Body;
+pub use ipnet::IpNet;
@@ -0,0 +1,532 @@
+use crate::{
+    http::{
+        header::{
+            HeaderName, CACHE_CONTROL, CONTENT_LOCATION, DATE, ETAG, EXPIRES, IF_MATCH,
+            IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_UNMODIFIED_SINCE, LAST_MODIFIED, VARY,
+        },
+        HeaderMap, HeaderValue, Method,
+    },
+    Body, FromRequest, Request, Responder, Response,
+};
+
+use base64::{engine::general_purpose::STANDARD_NO_PAD, Engine};
+use hyper::StatusCode;
+use sha1::{Digest, Sha1};
+
+use std::{
+    convert::Infallible,
+    fmt,
+    str::FromStr,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Headers a `304 Not Modified` keeps from the response it replaces.
+const NOT_MODIFIED_HEADERS: &[HeaderName] = &[
+    CACHE_CONTROL,
+    CONTENT_LOCATION,
+    DATE,
+    ETAG,
+    EXPIRES,
+    LAST_MODIFIED,
+    VARY,
+];
+
+/// Entity tag identifying one version of a resource.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ETag {
+    tag: String,
+    weak: bool,
+}
+
+impl ETag {
+    /// Tag of a byte-for-byte exact representation. Panics when `tag`
+    /// contains a `"`.
+    pub fn strong(tag: impl Into<String>) -> Self {
+        ETag::new(tag.into(), false)
+    }
+
+    /// Tag of a semantically equivalent representation. Panics when `tag`
+    /// contains a `"`.
+    pub fn weak(tag: impl Into<String>) -> Self {
+        ETag::new(tag.into(), true)
+    }
+
+    /// Strong tag derived from a hash of `data`.
+    pub fn hash(data: impl AsRef<[u8]>) -> Self {
+        ETag::strong(STANDARD_NO_PAD.encode(Sha1::digest(data.as_ref())))
+    }
+
+    fn new(tag: String, weak: bool) -> Self {
+        assert!(!tag.contains('"'), "entity tag must not contain `\"`");
+
+        ETag { tag, weak }
+    }
+
+    pub fn tag(&self) -> &str {
+        &self.tag
+    }
+
+    pub fn is_weak(&self) -> bool {
+        self.weak
+    }
+
+    pub(crate) fn strong_eq(&self, other: &ETag) -> bool {
+        !self.weak && !other.weak && self.tag == other.tag
+    }
+
+    pub(crate) fn weak_eq(&self, other: &ETag) -> bool {
+        self.tag == other.tag
+    }
+
+    pub(crate) fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        headers.get(ETAG)?.to_str().ok()?.parse().ok()
+    }
+}
+
+impl fmt::Display for ETag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.weak {
+            write!(f, "W/\"{}\"", self.tag)
+        } else {
+            write!(f, "\"{}\"", self.tag)
+        }
+    }
+}
+
+impl FromStr for ETag {
+    type Err = ();
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+        let (weak, quoted) = match value.strip_prefix("W/") {
+            Some(quoted) => (true, quoted),
+            None => (false, value),
+        };
+
+        let tag = quoted
+            .strip_prefix('"')
+            .and_then(|quoted| quoted.strip_suffix('"'))
+            .filter(|tag| !tag.contains('"'))
+            .ok_or(())?;
+
+        Ok(ETag {
+            tag: tag.to_string(),
+            weak,
+        })
+    }
+}
+
+/// Attaches validators to a responder, so Condey can answer conditional
+/// `GET` and `HEAD` requests with `304 Not Modified` or `412 Precondition Failed`.
+pub struct Conditional<R> {
+    inner: R,
+    etag: Option<ETag>,
+    hash_body: bool,
+    last_modified: Option<SystemTime>,
+}
+
+impl<R: Responder> Conditional<R> {
+    pub fn new(inner: R) -> Self {
+        Conditional {
+            inner,
+            etag: None,
+            hash_body: false,
+            last_modified: None,
+        }
+    }
+
+    pub fn etag(mut self, etag: ETag) -> Self {
+        self.etag = Some(etag);
+        self
+    }
+
+    /// Tags the response with a hash of its body, which is buffered for it.
+    pub fn hash_etag(mut self) -> Self {
+        self.hash_body = true;
+        self
+    }
+
+    pub fn last_modified(mut self, time: SystemTime) -> Self {
+        self.last_modified = Some(time);
+        self
+    }
+}
+
+#[async_trait::async_trait]
+impl<R: Responder> Responder for Conditional<R> {
+    async fn respond_to(self, req: &Request) -> Response {
+        let mut response = self.inner.respond_to(req).await;
+        if !response.status().is_success() {
+            return response;
+        }
+
+        let mut etag = self.etag;
+        if self.hash_body && etag.is_none() {
+            let body = std::mem::replace(response.body_mut(), Body::empty());
+            let body = match hyper::body::to_bytes(body).await {
+                Ok(body) => body,
+                Err(err) => {
+                    tracing::error!("failed to buffer response body: {}", err);
+                    return StatusCode::INTERNAL_SERVER_ERROR.respond_to(req).await;
+                }
+            };
+
+            etag = Some(ETag::hash(&body));
+            *response.body_mut() = Body::from(body);
+        }
+
+        let headers = response.headers_mut();
+        if let Some(etag) = etag {
+            headers.insert(ETAG, HeaderValue::from_str(&etag.to_string()).unwrap());
+        }
+        if let Some(time) = self.last_modified {
+            let date = httpdate::fmt_http_date(time);
+            headers.insert(LAST_MODIFIED, HeaderValue::from_str(&date).unwrap());
+        }
+
+        response
+    }
+}
+
+/// Conditional request headers, for checking preconditions before changing a
+/// resource. `GET` and `HEAD` responses are checked by Condey itself.
+///
+/// ```ignore
+/// |Path((id,)): Path<(i32,)>, preconditions: Preconditions, album: Json<Album>| async move {
+///     preconditions.evaluate(Some(&current_etag(id)), None)?;
+///     // update the album
+/// }
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct Preconditions {
+    safe: bool,
+    if_match: Option<Vec<ETagMatch>>,
+    if_none_match: Option<Vec<ETagMatch>>,
+    if_modified_since: Option<SystemTime>,
+    if_unmodified_since: Option<SystemTime>,
+}
+
+#[derive(Debug, Clone)]
+enum ETagMatch {
+    Any,
+    Tag(ETag),
+}
+
+impl Preconditions {
+    pub(crate) fn from_headers(method: &Method, headers: &HeaderMap) -> Self {
+        let date = |name| {
+            headers
+                .get(name)
+                .and_then(|date: &HeaderValue| date.to_str().ok())
+                .and_then(|date| httpdate::parse_http_date(date).ok())
+        };
+
+        Preconditions {
+            safe: method == Method::GET || method == Method::HEAD,
+            if_match: etag_list(headers, IF_MATCH),
+            if_none_match: etag_list(headers, IF_NONE_MATCH),
+            if_modified_since: date(IF_MODIFIED_SINCE),
+            if_unmodified_since: date(IF_UNMODIFIED_SINCE),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.if_match.is_none()
+            && self.if_none_match.is_none()
+            && self.if_modified_since.is_none()
+            && self.if_unmodified_since.is_none()
+    }
+
+    /// Checks the request against the current validators of the resource, in
+    /// the order of RFC 9110. Unchanged resources fail `GET` and `HEAD` with
+    /// `304 Not Modified`, other failures are `412 Precondition Failed`.
+    pub fn evaluate(
+        &self,
+        etag: Option<&ETag>,
+        last_modified: Option<SystemTime>,
+    ) -> Result<(), StatusCode> {
+        self.check(true, etag, last_modified)
+    }
+
+    /// Checks the request against a resource without a current representation,
+    /// e.g. a `PUT` creating it. `If-Match` always fails, `If-None-Match: *`
+    /// passes.
+    pub fn evaluate_missing(&self) -> Result<(), StatusCode> {
+        self.check(false, None, None)
+    }
+
+    fn check(
+        &self,
+        exists: bool,
+        etag: Option<&ETag>,
+        last_modified: Option<SystemTime>,
+    ) -> Result<(), StatusCode> {
+        // HTTP dates have a resolution of one second
+        let last_modified = last_modified.map(|time| {
+            let secs = time
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            UNIX_EPOCH + Duration::from_secs(secs)
+        });
+
+        if let Some(if_match) = &self.if_match {
+            if !matches(if_match, exists, etag, ETag::strong_eq) {
+                return Err(StatusCode::PRECONDITION_FAILED);
+            }
+        } else if let (Some(since), Some(modified)) = (self.if_unmodified_since, last_modified) {
+            if modified > since {
+                return Err(StatusCode::PRECONDITION_FAILED);
+            }
+        }
+
+        if let Some(if_none_match) = &self.if_none_match {
+            if matches(if_none_match, exists, etag, ETag::weak_eq) {
+                return Err(self.unchanged_status());
+            }
+        } else if let (true, Some(since), Some(modified)) =
+            (self.safe, self.if_modified_since, last_modified)
+        {
+            if modified <= since {
+                return Err(StatusCode::NOT_MODIFIED);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn unchanged_status(&self) -> StatusCode {
+        if self.safe {
+            StatusCode::NOT_MODIFIED
+        } else {
+            StatusCode::PRECONDITION_FAILED
+        }
+    }
+
+    /// Replaces a successful response whose validators fail the preconditions.
+    pub(crate) fn apply(&self, response: &mut Response) {
+        if self.is_empty() || !response.status().is_success() {
+            return;
+        }
+
+        let etag = ETag::from_headers(response.headers());
+        let last_modified = response
+            .headers()
+            .get(LAST_MODIFIED)
+            .and_then(|date| date.to_str().ok())
+            .and_then(|date| httpdate::parse_http_date(date).ok());
+
+        let status = match self.evaluate(etag.as_ref(), last_modified) {
+            Ok(()) => return,
+            Err(status) => status,
+        };
+
+        let mut replaced = hyper::Response::new(Body::empty());
+        *replaced.status_mut() = status;
+        if status == StatusCode::NOT_MODIFIED {
+            for name in NOT_MODIFIED_HEADERS {
+                for value in response.headers().get_all(name) {
+                    replaced.headers_mut().append(name, value.clone());
+                }
+            }
+        }
+
+        *response = replaced;
+    }
+}
+
+fn etag_list(headers: &HeaderMap, name: HeaderName) -> Option<Vec<ETagMatch>> {
+    let mut values = headers.get_all(name).iter().peekable();
+    values.peek()?;
+
+    let list = values
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .filter_map(|item| match item.trim() {
+            "*" => Some(ETagMatch::Any),
+            item => item.parse().ok().map(ETagMatch::Tag),
+        })
+        .collect();
+
+    Some(list)
+}
+
+/// Whether any item of an `If-Match` or `If-None-Match` list matches; `*`
+/// matches every current representation, tagged or not.
+fn matches(
+    list: &[ETagMatch],
+    exists: bool,
+    current: Option<&ETag>,
+    eq: fn(&ETag, &ETag) -> bool,
+) -> bool {
+    list.iter().any(|item| match item {
+        ETagMatch::Any => exists,
+        ETagMatch::Tag(tag) => current.is_some_and(|current| eq(tag, current)),
+    })
+}
+
+#[async_trait::async_trait]
+impl<'r> FromRequest<'r> for Preconditions {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request) -> Result<Self, Self::Error> {
+        Ok(Preconditions::from_headers(
+            request.method(),
+            request.headers(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{http::Method, types::Json, Condey, Route, TestClient};
+
+    #[test]
+    fn parse_etag() {
+        assert_eq!("\"abc\"".parse(), Ok(ETag::strong("abc")));
+        assert_eq!(" W/\"abc\"".parse(), Ok(ETag::weak("abc")));
+        assert_eq!("abc".parse::<ETag>(), Err(()));
+        assert_eq!(ETag::weak("abc").to_string(), "W/\"abc\"");
+    }
+
+    #[tokio::test]
+    async fn not_modified() {
+        let modified = UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        let get = Route::builder()
+            .method(Method::GET)
+            .path("/album")
+            .with_handler_fn(move || async move {
+                Conditional::new(Json("Crystal Logic"))
+                    .hash_etag()
+                    .last_modified(modified)
+            });
+        let client = TestClient::new(Condey::init().mount("", vec![get]));
+
+        let response = client.get("/album").send().await;
+        let etag = response.header("etag").unwrap().to_string();
+        assert_eq!(etag, ETag::hash("\"Crystal Logic\"").to_string());
+
+        client
+            .get("/album")
+            .header("if-none-match", format!("\"other\", W/{}", etag))
+            .send()
+            .await
+            .assert_status(StatusCode::NOT_MODIFIED)
+            .assert_header("etag", &etag)
+            .assert_no_header("content-type");
+        client
+            .get("/album")
+            .header("if-modified-since", "Sun, 09 Sep 2001 01:46:40 GMT")
+            .send()
+            .await
+            .assert_status(StatusCode::NOT_MODIFIED);
+        client
+            .get("/album")
+            .header("if-modified-since", "Sun, 09 Sep 2001 01:46:39 GMT")
+            .send()
+            .await
+            .assert_status(StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn precondition_failed() {
+        let modified = UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        let get = Route::builder()
+            .method(Method::GET)
+            .path("/album")
+            .with_handler_fn(move || async move {
+                Conditional::new(Json("Crystal Logic"))
+                    .hash_etag()
+                    .last_modified(modified)
+            });
+        let put = Route::builder()
+            .method(Method::PUT)
+            .path("/album")
+            .with_handler_fn(|preconditions: Preconditions| async move {
+                let current = ETag::hash("\"Crystal Logic\"");
+                preconditions.evaluate(Some(&current), None)?;
+
+                Ok::<_, StatusCode>(StatusCode::NO_CONTENT)
+            });
+        let client = TestClient::new(Condey::init().mount("", vec![get, put]));
+        let etag = ETag::hash("\"Crystal Logic\"").to_string();
+
+        client
+            .get("/album")
+            .header("if-match", "\"other\"")
+            .send()
+            .await
+            .assert_status(StatusCode::PRECONDITION_FAILED);
+        client
+            .get("/album")
+            .header("if-unmodified-since", "Sun, 09 Sep 2001 01:46:39 GMT")
+            .send()
+            .await
+            .assert_status(StatusCode::PRECONDITION_FAILED);
+
+        client
+            .put("/album")
+            .header("if-match", format!("W/{}", etag))
+            .send()
+            .await
+            .assert_status(StatusCode::PRECONDITION_FAILED);
+        client
+            .put("/album")
+            .header("if-match", etag.as_str())
+            .send()
+            .await
+            .assert_status(StatusCode::NO_CONTENT);
+        client
+            .put("/album")
+            .header("if-none-match", "*")
+            .send()
+            .await
+            .assert_status(StatusCode::PRECONDITION_FAILED);
+    }
+
+    #[tokio::test]
+    async fn any_without_validators() {
+        let routes = vec![
+            Route::builder()
+                .method(Method::GET)
+                .path("/plain")
+                .with_handler_fn(|| async { "no validators".to_string() }),
+            Route::builder()
+                .method(Method::PUT)
+                .path("/new")
+                .with_handler_fn(|preconditions: Preconditions| async move {
+                    preconditions.evaluate_missing()?;
+
+                    Ok::<_, StatusCode>(StatusCode::CREATED)
+                }),
+        ];
+        let client = TestClient::new(Condey::init().mount("", routes));
+
+        client
+            .get("/plain")
+            .header("if-match", "*")
+            .send()
+            .await
+            .assert_status(StatusCode::OK);
+        client
+            .get("/plain")
+            .header("if-none-match", "*")
+            .send()
+            .await
+            .assert_status(StatusCode::NOT_MODIFIED);
+
+        client
+            .put("/new")
+            .header("if-none-match", "*")
+            .send()
+            .await
+            .assert_status(StatusCode::CREATED);
+        client
+            .put("/new")
+            .header("if-match", "*")
+            .send()
+            .await
+            .assert_status(StatusCode::PRECONDITION_FAILED);
+    }
+}
@@ -76,6 +76,7 @@ mod test {
 
     #[tokio::test]
     async fn extract_query() {
+        #[allow(dead_code)]
         #[derive(Debug, Deserialize)]
         struct Foo {
             bread: String,
@@ -95,6 +96,7 @@ mod test {
 
     #[tokio::test]
     async fn allow_nones() {
+        #[allow(dead_code)]
         #[derive(Debug, Deserialize)]
         struct Foo {
             bread: Option<String>,
@@ -69,7 +69,7 @@ impl Interceptor for JsonInterceptor {
         let err = err.downcast_ref::<serde_json::Error>().unwrap();
 
         let resp = serde_json::json!({
-            "original_request": String::from_utf8_lossy(&*body).to_string(),
+            "original_request": String::from_utf8_lossy(&body).to_string(),
             "error_class": match err.classify() {
                 Category::Io => "IO",
                 Category::Syntax => "SYNTAX",
@@ -1,9 +1,20 @@
+mod conditional;
 mod form;
 mod json;
 mod path;
 mod query;
+mod sse;
+mod stream;
+mod ws;
 
+pub use conditional::{Conditional, ETag, Preconditions};
 pub use form::Form;
 pub use json::Json;
 pub use path::Path;
 pub use query::Query;
+pub use sse::{Event, LastEventId, Sse};
+pub use stream::{BodyStream, ReaderBody, StreamBody};
+pub use ws::{CloseFrame, Message, WebSocket, WebSocketError, WebSocketUpgrade};
+
+pub(crate) use stream::StreamedBody;
+pub(crate) use ws::PendingUpgrade;
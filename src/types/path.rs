@@ -1,4 +1,4 @@
-use crate::{Interceptor, FromPathParam, FromRequest, Request};
+use crate::{FromPathParam, FromRequest, Interceptor, Request};
 
 use anyhow::Result;
 use hyper::StatusCode;
@@ -41,7 +41,7 @@ impl<'r, T: DeserializeOwned> FromRequest<'r> for Query<T> {
         Self: Sized,
     {
         let query = req.uri().query().unwrap_or_default();
-        let query = serde_urlencoded::from_str(&*query)?;
+        let query = serde_urlencoded::from_str(query)?;
 
         Ok(Query(query))
     }
@@ -55,6 +55,7 @@ mod test {
 
     #[tokio::test]
     async fn extract_query() {
+        #[allow(dead_code)]
         #[derive(Debug, Deserialize)]
         struct Foo {
             bread: String,
@@ -75,6 +76,7 @@ mod test {
 
     #[tokio::test]
     async fn allow_nones() {
+        #[allow(dead_code)]
         #[derive(Debug, Deserialize)]
         struct Foo {
             bread: Option<String>,
@@ -0,0 +1,271 @@
+use crate::{
+    http::{header, response::Builder},
+    Body, FromRequest, Request, Responder, Response,
+};
+
+use futures::{ready, Stream};
+use hyper::{body::Bytes, StatusCode};
+use tokio::time::{sleep, Sleep};
+
+use std::{
+    convert::Infallible,
+    fmt::Write,
+    future::Future,
+    pin::Pin,
+    sync::Mutex,
+    task::{Context, Poll},
+    time::Duration,
+};
+
+const DEFAULT_KEEP_ALIVE: Duration = Duration::from_secs(15);
+
+/// Single Server-Sent Event.
+#[derive(Debug, Clone, Default)]
+pub struct Event {
+    id: Option<String>,
+    event: Option<String>,
+    data: Option<String>,
+    retry: Option<Duration>,
+}
+
+impl Event {
+    /// Event carrying `data`, split into one `data:` field per line. Lines may
+    /// end in `\r\n`, `\r` or `\n`.
+    pub fn data(data: impl Into<String>) -> Self {
+        Event {
+            data: Some(data.into()),
+            ..Event::default()
+        }
+    }
+
+    /// Sets the id clients send back in `Last-Event-ID` when reconnecting.
+    /// Line breaks are removed.
+    pub fn id(mut self, id: impl Into<String>) -> Self {
+        self.id = Some(single_line(id.into()));
+        self
+    }
+
+    /// Sets the event type, line breaks are removed.
+    pub fn event(mut self, event: impl Into<String>) -> Self {
+        self.event = Some(single_line(event.into()));
+        self
+    }
+
+    /// Sets the time clients wait before reconnecting.
+    pub fn retry(mut self, retry: Duration) -> Self {
+        self.retry = Some(retry);
+        self
+    }
+
+    fn to_bytes(&self) -> Bytes {
+        let mut buf = String::new();
+
+        if let Some(id) = &self.id {
+            writeln!(buf, "id: {}", id).unwrap();
+        }
+        if let Some(event) = &self.event {
+            writeln!(buf, "event: {}", event).unwrap();
+        }
+        if let Some(retry) = self.retry {
+            writeln!(buf, "retry: {}", retry.as_millis()).unwrap();
+        }
+        if let Some(data) = &self.data {
+            data.replace("\r\n", "\n")
+                .split(['\r', '\n'])
+                .for_each(|line| writeln!(buf, "data: {}", line).unwrap());
+        }
+        buf.push('\n');
+
+        buf.into()
+    }
+}
+
+fn single_line(value: String) -> String {
+    value.replace(['\r', '\n'], "")
+}
+
+/// Streams events to the client as `text/event-stream`, sending a keep-alive
+/// comment whenever no event was sent for a while.
+///
+/// ```ignore
+/// async fn ticks() -> Sse<impl Stream<Item = Event>> {
+///     Sse::new(stream::iter(0..).map(|tick| Event::data(tick.to_string())))
+/// }
+/// ```
+pub struct Sse<S> {
+    stream: Mutex<S>,
+    keep_alive: Option<Duration>,
+}
+
+impl<S> Sse<S>
+where
+    S: Stream<Item = Event> + Send + 'static,
+{
+    pub fn new(stream: S) -> Self {
+        Sse {
+            stream: Mutex::new(stream),
+            keep_alive: Some(DEFAULT_KEEP_ALIVE),
+        }
+    }
+
+    /// Interval of keep-alive comments, 15 seconds by default.
+    pub fn keep_alive(mut self, interval: Duration) -> Self {
+        self.keep_alive = Some(interval);
+        self
+    }
+
+    pub fn no_keep_alive(mut self) -> Self {
+        self.keep_alive = None;
+        self
+    }
+}
+
+#[async_trait::async_trait]
+impl<S> Responder for Sse<S>
+where
+    S: Stream<Item = Event> + Send + 'static,
+{
+    async fn respond_to(self, _: &Request) -> Response {
+        let body = SseBody {
+            stream: Box::pin(self.stream.into_inner().unwrap()),
+            keep_alive: self
+                .keep_alive
+                .map(|interval| (interval, Box::pin(sleep(interval)))),
+        };
+
+        Builder::new()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, "text/event-stream")
+            .header(header::CACHE_CONTROL, "no-cache")
+            .body(Body::wrap_stream(body))
+            .unwrap()
+    }
+}
+
+struct SseBody<S> {
+    stream: Pin<Box<S>>,
+    keep_alive: Option<(Duration, Pin<Box<Sleep>>)>,
+}
+
+impl<S: Stream<Item = Event>> Stream for SseBody<S> {
+    type Item = Result<Bytes, Infallible>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = &mut *self;
+
+        if let Poll::Ready(event) = this.stream.as_mut().poll_next(cx) {
+            if let Some((interval, timer)) = &mut this.keep_alive {
+                timer.set(sleep(*interval));
+            }
+
+            return Poll::Ready(event.map(|event| Ok(event.to_bytes())));
+        }
+
+        match &mut this.keep_alive {
+            Some((interval, timer)) => {
+                ready!(timer.as_mut().poll(cx));
+                timer.set(sleep(*interval));
+
+                Poll::Ready(Some(Ok(Bytes::from_static(b": keep-alive\n\n"))))
+            }
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// Value of the `Last-Event-ID` header a reconnecting client sends.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LastEventId(pub Option<String>);
+
+#[async_trait::async_trait]
+impl<'r> FromRequest<'r> for LastEventId {
+    type Error = Infallible;
+
+    async fn from_request(request: &'r Request) -> Result<Self, Self::Error> {
+        let id = request
+            .headers()
+            .get("last-event-id")
+            .and_then(|id| id.to_str().ok())
+            .map(str::to_string);
+
+        Ok(LastEventId(id))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{http::Method, Condey, Route, TestClient};
+
+    use futures::{stream, StreamExt};
+    use hyper::body::HttpBody;
+
+    fn routes() -> Vec<Route> {
+        vec![
+            Route::builder()
+                .method(Method::GET)
+                .path("/events")
+                .with_handler_fn(|last_id: LastEventId| async move {
+                    let start = last_id.0.and_then(|id| id.parse().ok()).unwrap_or(0);
+
+                    Sse::new(stream::iter(start + 1..start + 3).map(|id: u32| {
+                        Event::data(format!("tick\n{}", id))
+                            .id(id.to_string())
+                            .event("tick")
+                    }))
+                }),
+            Route::builder()
+                .method(Method::GET)
+                .path("/idle")
+                .with_handler_fn(|| async {
+                    Sse::new(stream::pending()).keep_alive(Duration::from_millis(10))
+                }),
+        ]
+    }
+
+    #[tokio::test]
+    async fn stream_events() {
+        let client = TestClient::new(Condey::init().mount("", routes()));
+
+        let response = client
+            .get("/events")
+            .header("last-event-id", "4")
+            .send()
+            .await;
+        response
+            .assert_status(StatusCode::OK)
+            .assert_header("content-type", "text/event-stream");
+
+        assert_eq!(
+            response.text().await,
+            "id: 5\nevent: tick\ndata: tick\ndata: 5\n\nid: 6\nevent: tick\ndata: tick\ndata: 6\n\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn keep_alive_comments() {
+        let client = TestClient::new(Condey::init().mount("", routes()));
+
+        let mut body = client.get("/idle").send().await.into_inner().into_body();
+        let chunk = body.data().await.unwrap().unwrap();
+
+        assert_eq!(&chunk[..], b": keep-alive\n\n");
+    }
+
+    #[test]
+    fn event_fields() {
+        let event = Event::default().id("1\n2").retry(Duration::from_secs(3));
+
+        assert_eq!(&event.to_bytes()[..], b"id: 12\nretry: 3000\n\n");
+    }
+
+    #[test]
+    fn split_data_lines() {
+        let event = Event::data("a\rb\r\nc\nd").event("up\rdate");
+
+        assert_eq!(
+            &event.to_bytes()[..],
+            b"event: update\ndata: a\ndata: b\ndata: c\ndata: d\n\n"
+        );
+    }
+}
@@ -0,0 +1,251 @@
+use crate::{
+    core::extract::{Extract, ExtractClass},
+    http::{header, response::Builder, HeaderValue},
+    Body, Interceptor, Request, Responder, Response,
+};
+
+use anyhow::anyhow;
+use futures::{Stream, TryStreamExt};
+use hyper::{body::Bytes, StatusCode};
+use tokio::io::AsyncRead;
+use tokio_util::io::ReaderStream;
+use tower::BoxError;
+
+use std::{
+    pin::Pin,
+    sync::Mutex,
+    task::{Context, Poll},
+};
+
+/// Request body left unbuffered for a [`BodyStream`] extractor.
+pub(crate) struct StreamedBody(Mutex<Option<Body>>);
+
+impl StreamedBody {
+    pub fn new(body: Body) -> Self {
+        StreamedBody(Mutex::new(Some(body)))
+    }
+}
+
+/// Raw chunks of the request body, read as the client sends them.
+///
+/// Handlers using it get no pre-buffered body, so no other extractor may
+/// consume the body in the same handler.
+pub struct BodyStream(Body);
+
+impl BodyStream {
+    pub fn into_inner(self) -> Body {
+        self.0
+    }
+}
+
+impl Stream for BodyStream {
+    type Item = Result<Bytes, hyper::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.0).poll_next(cx)
+    }
+}
+
+pub struct ExtractStream;
+impl ExtractClass for ExtractStream {}
+
+#[async_trait::async_trait]
+impl<'r> Extract<'r, ExtractStream> for BodyStream {
+    async fn extract(request: &'r Request, _: &'r [u8]) -> anyhow::Result<Self> {
+        request
+            .extensions()
+            .get::<StreamedBody>()
+            .and_then(|body| body.0.lock().unwrap().take())
+            .map(BodyStream)
+            .ok_or_else(|| anyhow!("request body already taken"))
+    }
+
+    fn default_interceptor() -> Box<dyn Interceptor> {
+        Box::new(StatusCode::INTERNAL_SERVER_ERROR)
+    }
+
+    const TAKES_BODY: bool = true;
+
+    const STREAMS_BODY: bool = true;
+}
+
+/// Sends the chunks of a stream as the response body, chunked unless the
+/// length is known. An error ends the body and aborts the connection.
+pub struct StreamBody<S> {
+    stream: Mutex<S>,
+    content_type: HeaderValue,
+    content_length: Option<u64>,
+}
+
+impl<S, E> StreamBody<S>
+where
+    S: Stream<Item = Result<Bytes, E>> + Send + 'static,
+    E: Into<BoxError> + 'static,
+{
+    pub fn new(stream: S) -> Self {
+        StreamBody {
+            stream: Mutex::new(stream),
+            content_type: HeaderValue::from_static("application/octet-stream"),
+            content_length: None,
+        }
+    }
+
+    pub fn content_type(mut self, content_type: &'static str) -> Self {
+        self.content_type = HeaderValue::from_static(content_type);
+        self
+    }
+
+    /// Sends the body with `Content-Length` instead of chunked.
+    pub fn content_length(mut self, len: u64) -> Self {
+        self.content_length = Some(len);
+        self
+    }
+}
+
+#[async_trait::async_trait]
+impl<S, E> Responder for StreamBody<S>
+where
+    S: Stream<Item = Result<Bytes, E>> + Send + 'static,
+    E: Into<BoxError> + 'static,
+{
+    async fn respond_to(self, _: &Request) -> Response {
+        let span = tracing::Span::current();
+        let stream = self.stream.into_inner().unwrap().map_err(move |err| {
+            let err = err.into();
+            tracing::error!(parent: &span, "response body failed: {}", err);
+            err
+        });
+
+        let mut response = Builder::new()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, self.content_type);
+
+        if let Some(len) = self.content_length {
+            response = response.header(header::CONTENT_LENGTH, len);
+        }
+
+        response.body(Body::wrap_stream(stream)).unwrap()
+    }
+}
+
+/// Sends everything read from an `AsyncRead`, e.g. a file, as the response body.
+pub struct ReaderBody<R> {
+    body: StreamBody<ReaderStream<R>>,
+}
+
+impl<R: AsyncRead + Send + 'static> ReaderBody<R> {
+    pub fn new(reader: R) -> Self {
+        ReaderBody {
+            body: StreamBody::new(ReaderStream::new(reader)),
+        }
+    }
+
+    pub fn content_type(mut self, content_type: &'static str) -> Self {
+        self.body = self.body.content_type(content_type);
+        self
+    }
+
+    /// Sends the body with `Content-Length` instead of chunked.
+    pub fn content_length(mut self, len: u64) -> Self {
+        self.body = self.body.content_length(len);
+        self
+    }
+}
+
+#[async_trait::async_trait]
+impl<R: AsyncRead + Send + 'static> Responder for ReaderBody<R> {
+    async fn respond_to(self, req: &Request) -> Response {
+        self.body.respond_to(req).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{http::Method, types::Json, Condey, Route, TestClient};
+
+    use futures::StreamExt;
+
+    use std::time::Duration;
+
+    fn routes() -> Vec<Route> {
+        vec![
+            Route::builder()
+                .method(Method::POST)
+                .path("/first-chunk")
+                .with_handler_fn(|mut body: BodyStream| async move {
+                    body.next().await.unwrap().unwrap().to_vec()
+                }),
+            Route::builder()
+                .method(Method::GET)
+                .path("/csv")
+                .with_handler_fn(|| async {
+                    let rows = vec![
+                        Ok("a,b\n"),
+                        Ok("1,2\n"),
+                        Err(std::io::Error::other("disk gone")),
+                    ];
+                    StreamBody::new(futures::stream::iter(rows).map_ok(Bytes::from))
+                        .content_type("text/csv")
+                }),
+            Route::builder()
+                .method(Method::GET)
+                .path("/reader")
+                .with_handler_fn(|| async { ReaderBody::new(&b"Hello!"[..]).content_length(6) }),
+        ]
+    }
+
+    #[tokio::test]
+    async fn stream_without_buffering() {
+        let client = TestClient::new(Condey::init().mount("", routes()));
+        let (mut sender, body) = Body::channel();
+
+        sender.send_data("first".into()).await.unwrap();
+
+        // the body is never finished, buffering it would hang
+        let response = tokio::time::timeout(
+            Duration::from_secs(1),
+            client.post("/first-chunk").body(body).send(),
+        )
+        .await
+        .expect("body was buffered");
+
+        response.assert_status(StatusCode::OK);
+        assert_eq!(response.text().await, "first");
+    }
+
+    #[test]
+    #[should_panic(expected = "POST /twice")]
+    fn single_body_extractor() {
+        Route::builder()
+            .method(Method::POST)
+            .path("/twice")
+            .with_handler_fn(|_: Json<String>, _: BodyStream| async { StatusCode::OK });
+    }
+
+    #[tokio::test]
+    async fn stream_response_error() {
+        let client = TestClient::new(Condey::init().mount("", routes()));
+
+        let response = client.get("/csv").send().await;
+        response
+            .assert_status(StatusCode::OK)
+            .assert_header("content-type", "text/csv")
+            .assert_no_header("content-length");
+
+        let body = hyper::body::to_bytes(response.into_inner().into_body()).await;
+        assert!(body.is_err());
+    }
+
+    #[tokio::test]
+    async fn reader_response() {
+        let client = TestClient::new(Condey::init().mount("", routes()));
+
+        let response = client.get("/reader").send().await;
+        response
+            .assert_status(StatusCode::OK)
+            .assert_header("content-length", "6");
+
+        assert_eq!(response.text().await, "Hello!");
+    }
+}
@@ -0,0 +1,444 @@
+use crate::{
+    core::shutdown::ConnectionCounter,
+    http::{header, HeaderValue, Method},
+    Body, FromRequest, Interceptor, Request, Response,
+};
+
+use anyhow::anyhow;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use futures::{ready, Sink, Stream};
+use hyper::{http::response::Builder, upgrade::OnUpgrade, upgrade::Upgraded, StatusCode};
+use sha1::{Digest, Sha1};
+use tokio_tungstenite::{
+    tungstenite::{
+        self,
+        protocol::{frame::coding::CloseCode, CloseFrame as TungsteniteCloseFrame, Role},
+    },
+    WebSocketStream,
+};
+
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::Mutex,
+    task::{Context, Poll},
+};
+
+const ACCEPT_GUID: &[u8] = b"258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+/// Upgrade of the connection taken off the request before it is dispatched,
+/// so extractors working on `&Request` can claim it.
+pub(crate) struct PendingUpgrade(Mutex<Option<OnUpgrade>>);
+
+impl PendingUpgrade {
+    pub fn new(on_upgrade: OnUpgrade) -> Self {
+        PendingUpgrade(Mutex::new(Some(on_upgrade)))
+    }
+}
+
+/// Extracts a valid WebSocket handshake request. Responds with `400 Bad Request`
+/// to anything else.
+///
+/// ```ignore
+/// async fn echo(ws: WebSocketUpgrade) -> Response {
+///     ws.on_upgrade(|mut socket| async move {
+///         while let Some(Ok(message)) = socket.recv().await {
+///             if socket.send(message).await.is_err() {
+///                 break;
+///             }
+///         }
+///     })
+/// }
+/// ```
+pub struct WebSocketUpgrade {
+    key: HeaderValue,
+    offered_protocols: Option<HeaderValue>,
+    protocol: Option<HeaderValue>,
+    on_upgrade: OnUpgrade,
+    config: tungstenite::protocol::WebSocketConfig,
+    connections: Option<ConnectionCounter>,
+}
+
+impl WebSocketUpgrade {
+    /// Maximum size of an incoming message, 64 MiB by default.
+    pub fn max_message_size(mut self, size: usize) -> Self {
+        self.config.max_message_size = Some(size);
+        self
+    }
+
+    /// Maximum size of an incoming frame, 16 MiB by default.
+    pub fn max_frame_size(mut self, size: usize) -> Self {
+        self.config.max_frame_size = Some(size);
+        self
+    }
+
+    /// Picks the first of `protocols` the client offered in `Sec-WebSocket-Protocol`.
+    pub fn protocols<I>(mut self, protocols: I) -> Self
+    where
+        I: IntoIterator,
+        I::Item: AsRef<str>,
+    {
+        let offered = self
+            .offered_protocols
+            .as_ref()
+            .and_then(|offered| offered.to_str().ok())
+            .map(|offered| offered.split(',').map(str::trim).collect::<Vec<_>>())
+            .unwrap_or_default();
+
+        self.protocol = protocols
+            .into_iter()
+            .find(|protocol| offered.contains(&protocol.as_ref()))
+            .and_then(|protocol| HeaderValue::from_str(protocol.as_ref()).ok());
+
+        self
+    }
+
+    /// Responds with `101 Switching Protocols` and runs `callback` with the
+    /// socket once the connection is upgraded. Graceful shutdown waits for the
+    /// callback like for any other connection and aborts it at the drain deadline.
+    pub fn on_upgrade<F, Fut>(self, callback: F) -> Response
+    where
+        F: FnOnce(WebSocket) -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let config = self.config;
+        let on_upgrade = self.on_upgrade;
+
+        let task = async move {
+            let upgraded = match on_upgrade.await {
+                Ok(upgraded) => upgraded,
+                Err(err) => {
+                    tracing::debug!("WebSocket upgrade failed: {}", err);
+                    return;
+                }
+            };
+
+            let stream =
+                WebSocketStream::from_raw_socket(upgraded, Role::Server, Some(config)).await;
+            callback(WebSocket { stream }).await;
+        };
+        match self.connections {
+            Some(connections) => connections.spawn(task),
+            None => {
+                tokio::spawn(task);
+            }
+        }
+
+        let mut response = Builder::new()
+            .status(StatusCode::SWITCHING_PROTOCOLS)
+            .header(header::CONNECTION, "upgrade")
+            .header(header::UPGRADE, "websocket")
+            .header(
+                header::SEC_WEBSOCKET_ACCEPT,
+                accept_key(self.key.as_bytes()),
+            );
+
+        if let Some(protocol) = self.protocol {
+            response = response.header(header::SEC_WEBSOCKET_PROTOCOL, protocol);
+        }
+
+        response.body(Body::empty()).unwrap()
+    }
+}
+
+fn accept_key(key: &[u8]) -> String {
+    let mut sha1 = Sha1::new();
+    sha1.update(key);
+    sha1.update(ACCEPT_GUID);
+
+    STANDARD.encode(sha1.finalize())
+}
+
+fn header_contains(request: &Request, name: header::HeaderName, token: &str) -> bool {
+    request
+        .headers()
+        .get_all(name)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .any(|value| value.trim().eq_ignore_ascii_case(token))
+}
+
+#[async_trait::async_trait]
+impl<'r> FromRequest<'r> for WebSocketUpgrade {
+    type Error = anyhow::Error;
+
+    async fn from_request(request: &'r Request) -> Result<Self, Self::Error> {
+        if request.method() != Method::GET {
+            return Err(anyhow!("WebSocket handshake must be a GET request"));
+        }
+
+        if !header_contains(request, header::CONNECTION, "upgrade")
+            || !header_contains(request, header::UPGRADE, "websocket")
+        {
+            return Err(anyhow!("request is not a WebSocket upgrade"));
+        }
+
+        if request.headers().get(header::SEC_WEBSOCKET_VERSION)
+            != Some(&HeaderValue::from_static("13"))
+        {
+            return Err(anyhow!("unsupported WebSocket version"));
+        }
+
+        let key = request
+            .headers()
+            .get(header::SEC_WEBSOCKET_KEY)
+            .cloned()
+            .ok_or_else(|| anyhow!("missing Sec-WebSocket-Key header"))?;
+
+        let on_upgrade = request
+            .extensions()
+            .get::<PendingUpgrade>()
+            .and_then(|pending| pending.0.lock().unwrap().take())
+            .ok_or_else(|| anyhow!("connection cannot be upgraded"))?;
+
+        Ok(WebSocketUpgrade {
+            key,
+            offered_protocols: request
+                .headers()
+                .get(header::SEC_WEBSOCKET_PROTOCOL)
+                .cloned(),
+            protocol: None,
+            on_upgrade,
+            config: Default::default(),
+            connections: request.extensions().get::<ConnectionCounter>().cloned(),
+        })
+    }
+
+    fn default_interceptor() -> Box<dyn Interceptor> {
+        Box::new(StatusCode::BAD_REQUEST)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CloseFrame {
+    pub code: u16,
+    pub reason: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    Ping(Vec<u8>),
+    Pong(Vec<u8>),
+    Close(Option<CloseFrame>),
+}
+
+impl Message {
+    fn from_tungstenite(message: tungstenite::Message) -> Option<Self> {
+        match message {
+            tungstenite::Message::Text(text) => Some(Message::Text(text)),
+            tungstenite::Message::Binary(data) => Some(Message::Binary(data)),
+            tungstenite::Message::Ping(data) => Some(Message::Ping(data)),
+            tungstenite::Message::Pong(data) => Some(Message::Pong(data)),
+            tungstenite::Message::Close(frame) => {
+                Some(Message::Close(frame.map(|frame| CloseFrame {
+                    code: frame.code.into(),
+                    reason: frame.reason.into_owned(),
+                })))
+            }
+            // raw frames are never yielded while reading
+            tungstenite::Message::Frame(_) => None,
+        }
+    }
+
+    fn into_tungstenite(self) -> tungstenite::Message {
+        match self {
+            Message::Text(text) => tungstenite::Message::Text(text),
+            Message::Binary(data) => tungstenite::Message::Binary(data),
+            Message::Ping(data) => tungstenite::Message::Ping(data),
+            Message::Pong(data) => tungstenite::Message::Pong(data),
+            Message::Close(frame) => {
+                tungstenite::Message::Close(frame.map(|frame| TungsteniteCloseFrame {
+                    code: CloseCode::from(frame.code),
+                    reason: frame.reason.into(),
+                }))
+            }
+        }
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error(transparent)]
+pub struct WebSocketError(#[from] tungstenite::Error);
+
+/// Upgraded WebSocket connection, a `Stream` of incoming and a `Sink` of
+/// outgoing messages. Pings are answered automatically.
+pub struct WebSocket {
+    stream: WebSocketStream<Upgraded>,
+}
+
+impl WebSocket {
+    pub async fn recv(&mut self) -> Option<Result<Message, WebSocketError>> {
+        futures::StreamExt::next(self).await
+    }
+
+    pub async fn send(&mut self, message: Message) -> Result<(), WebSocketError> {
+        futures::SinkExt::send(self, message).await
+    }
+
+    /// Sends a close frame and flushes it.
+    pub async fn close(mut self) -> Result<(), WebSocketError> {
+        futures::SinkExt::close(&mut self).await
+    }
+}
+
+impl Stream for WebSocket {
+    type Item = Result<Message, WebSocketError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            match ready!(Pin::new(&mut self.stream).poll_next(cx)) {
+                Some(Ok(message)) => {
+                    if let Some(message) = Message::from_tungstenite(message) {
+                        return Poll::Ready(Some(Ok(message)));
+                    }
+                }
+                Some(Err(err)) => return Poll::Ready(Some(Err(err.into()))),
+                None => return Poll::Ready(None),
+            }
+        }
+    }
+}
+
+impl Sink<Message> for WebSocket {
+    type Error = WebSocketError;
+
+    fn poll_ready(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.stream)
+            .poll_ready(cx)
+            .map_err(Into::into)
+    }
+
+    fn start_send(mut self: Pin<&mut Self>, message: Message) -> Result<(), Self::Error> {
+        Pin::new(&mut self.stream)
+            .start_send(message.into_tungstenite())
+            .map_err(Into::into)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.stream)
+            .poll_flush(cx)
+            .map_err(Into::into)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Pin::new(&mut self.stream)
+            .poll_close(cx)
+            .map_err(Into::into)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Condey, Listener, Route, ServerConfig, ServerError, TestClient};
+
+    use futures::{channel::oneshot, SinkExt, StreamExt};
+    use tokio::net::TcpStream;
+
+    use std::time::Duration;
+
+    fn echo() -> Route {
+        Route::builder()
+            .method(Method::GET)
+            .path("/echo")
+            .with_handler_fn(|ws: WebSocketUpgrade| async move {
+                ws.max_message_size(16).on_upgrade(|mut socket| async move {
+                    while let Some(Ok(message)) = socket.recv().await {
+                        if socket.send(message).await.is_err() {
+                            break;
+                        }
+                    }
+                })
+            })
+    }
+
+    #[test]
+    fn derive_accept_key() {
+        assert_eq!(
+            accept_key(b"dGhlIHNhbXBsZSBub25jZQ=="),
+            "s3pPLMBiTxaQ9kYGzzhZRbK+xOo="
+        );
+    }
+
+    #[tokio::test]
+    async fn echo_messages() {
+        let listener = Listener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().tcp().unwrap();
+        tokio::spawn(Condey::init().mount("", vec![echo()]).listen_on(listener));
+
+        let tcp = TcpStream::connect(addr).await.unwrap();
+        let (mut client, response) =
+            tokio_tungstenite::client_async(format!("ws://{}/echo", addr), tcp)
+                .await
+                .unwrap();
+        assert_eq!(response.status(), 101);
+
+        client
+            .send(tungstenite::Message::Text("ping".into()))
+            .await
+            .unwrap();
+        assert_eq!(
+            client.next().await.unwrap().unwrap(),
+            tungstenite::Message::Text("ping".into())
+        );
+
+        client
+            .send(tungstenite::Message::Binary(vec![0; 32]))
+            .await
+            .unwrap();
+        let closed = client.next().await;
+        assert!(!matches!(closed, Some(Ok(tungstenite::Message::Binary(_)))));
+    }
+
+    #[tokio::test]
+    async fn drain_upgraded_connections() {
+        let listener = Listener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap().tcp().unwrap();
+        let (shutdown_tx, shutdown_rx) = oneshot::channel::<()>();
+        let server = tokio::spawn(
+            Condey::init()
+                .mount("", vec![echo()])
+                .server_config(ServerConfig::default().drain_timeout(Duration::from_millis(50)))
+                .graceful_shutdown(async {
+                    shutdown_rx.await.ok();
+                })
+                .listen_on(listener),
+        );
+
+        let tcp = TcpStream::connect(addr).await.unwrap();
+        let (mut client, _) = tokio_tungstenite::client_async(format!("ws://{}/echo", addr), tcp)
+            .await
+            .unwrap();
+        client
+            .send(tungstenite::Message::Text("ping".into()))
+            .await
+            .unwrap();
+        client.next().await.unwrap().unwrap();
+
+        shutdown_tx.send(()).unwrap();
+        let result = server.await.unwrap();
+        assert!(matches!(
+            result,
+            Err(ServerError::DrainTimeoutError { connections: 1 })
+        ));
+
+        let closed = tokio::time::timeout(Duration::from_secs(1), client.next())
+            .await
+            .expect("socket still open after the drain timeout");
+        assert!(!matches!(closed, Some(Ok(tungstenite::Message::Text(_)))));
+    }
+
+    #[tokio::test]
+    async fn reject_plain_request() {
+        let client = TestClient::new(Condey::init().mount("", vec![echo()]));
+
+        client
+            .get("/echo")
+            .send()
+            .await
+            .assert_status(StatusCode::BAD_REQUEST);
+    }
+}